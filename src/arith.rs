@@ -0,0 +1,479 @@
+use thiserror::Error;
+
+use crate::variables::Variables;
+
+#[derive(Debug, Error)]
+pub enum ArithmeticError {
+    #[error("{0}: syntax error in expression")]
+    Syntax(String),
+    #[error("{0}: division by 0")]
+    DivisionByZero(String),
+    #[error("{0}: invalid number")]
+    InvalidNumber(String),
+    #[error("{0}: exponent less than 0")]
+    NegativeExponent(String),
+    #[error("{0}: expression recursion level exceeded")]
+    Recursion(String),
+}
+
+/// Evaluates a shell arithmetic expression, reading and assigning shell
+/// variables as it goes.
+pub fn evaluate(expression: &str, variables: &mut Variables) -> Result<i64, ArithmeticError> {
+    evaluate_nested(expression, variables, 0)
+}
+
+fn evaluate_nested(
+    expression: &str,
+    variables: &mut Variables,
+    depth: usize,
+) -> Result<i64, ArithmeticError> {
+    if expression.trim().is_empty() {
+        return Ok(0);
+    }
+
+    let tokens = lex(expression)?;
+    let mut parser = ArithmeticParser {
+        expression,
+        tokens,
+        position: 0,
+    };
+    let node = parser.comma()?;
+
+    if parser.position != parser.tokens.len() {
+        return Err(ArithmeticError::Syntax(expression.to_string()));
+    }
+
+    let mut evaluator = Evaluator {
+        expression,
+        variables,
+        depth,
+    };
+    evaluator.eval(&node)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithmeticToken {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+// longest operators first so that lexing is greedy
+const OPERATORS: [&str; 42] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")", "$", "{", "}",
+];
+
+fn lex(expression: &str) -> Result<Vec<ArithmeticToken>, ArithmeticError> {
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '#' && c != '_' && c != '@')
+                .unwrap_or(rest.len());
+            tokens.push(ArithmeticToken::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(ArithmeticToken::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+            return Err(ArithmeticError::Syntax(expression.to_string()));
+        };
+
+        tokens.push(ArithmeticToken::Operator(operator));
+        rest = &rest[operator.len()..];
+    }
+
+    Ok(tokens)
+}
+
+/// Parses decimal, octal (`017`), hexadecimal (`0x1f`) and `base#digits`
+/// integer constants.
+fn parse_number(text: &str) -> Result<i64, ArithmeticError> {
+    let invalid = || ArithmeticError::InvalidNumber(text.to_string());
+
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        let base: u32 = base.parse().map_err(|_| invalid())?;
+        if !(2..=64).contains(&base) {
+            return Err(invalid());
+        }
+        (base, digits)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    digits.chars().try_fold(0i64, |value, c| {
+        let digit = match c {
+            '0'..='9' => u32::from(c) - u32::from('0'),
+            'a'..='z' => u32::from(c) - u32::from('a') + 10,
+            'A'..='Z' if base <= 36 => u32::from(c) - u32::from('A') + 10,
+            'A'..='Z' => u32::from(c) - u32::from('A') + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+
+        if digit >= base {
+            return Err(invalid());
+        }
+
+        Ok(value
+            .wrapping_mul(i64::from(base))
+            .wrapping_add(i64::from(digit)))
+    })
+}
+
+#[derive(Debug)]
+enum Node {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Assign(&'static str, String, Box<Node>),
+    Increment {
+        name: String,
+        delta: i64,
+        prefix: bool,
+    },
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+}
+
+struct ArithmeticParser<'a> {
+    expression: &'a str,
+    tokens: Vec<ArithmeticToken>,
+    position: usize,
+}
+
+// binary operators from the loosest to the tightest binding
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl ArithmeticParser<'_> {
+    fn error(&self) -> ArithmeticError {
+        ArithmeticError::Syntax(self.expression.to_string())
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(ArithmeticToken::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), ArithmeticError> {
+        if self.peek_operator() == Some(operator) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn comma(&mut self) -> Result<Node, ArithmeticError> {
+        let mut node = self.assignment()?;
+
+        while self.peek_operator() == Some(",") {
+            self.position += 1;
+            let right = self.assignment()?;
+            node = Node::Binary(",", Box::new(node), Box::new(right));
+        }
+
+        Ok(node)
+    }
+
+    fn assignment(&mut self) -> Result<Node, ArithmeticError> {
+        if let (Some(ArithmeticToken::Name(name)), Some(ArithmeticToken::Operator(op))) = (
+            self.tokens.get(self.position),
+            self.tokens.get(self.position + 1),
+        ) {
+            if matches!(
+                *op,
+                "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "^=" | "|="
+            ) {
+                let name = name.clone();
+                let op = *op;
+                self.position += 2;
+                let value = self.assignment()?;
+                return Ok(Node::Assign(op, name, Box::new(value)));
+            }
+        }
+
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Node, ArithmeticError> {
+        let condition = self.binary(0)?;
+
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.position += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.assignment()?;
+
+        Ok(Node::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, ArithmeticError> {
+        if level == BINARY_LEVELS.len() {
+            return self.power();
+        }
+
+        let mut node = self.binary(level + 1)?;
+
+        while let Some(op) = self
+            .peek_operator()
+            .filter(|op| BINARY_LEVELS[level].contains(op))
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(right));
+        }
+
+        Ok(node)
+    }
+
+    fn power(&mut self) -> Result<Node, ArithmeticError> {
+        let base = self.unary()?;
+
+        if self.peek_operator() != Some("**") {
+            return Ok(base);
+        }
+
+        self.position += 1;
+        let exponent = self.power()?;
+        Ok(Node::Binary("**", Box::new(base), Box::new(exponent)))
+    }
+
+    fn unary(&mut self) -> Result<Node, ArithmeticError> {
+        match self.peek_operator() {
+            Some(op @ ("++" | "--")) => {
+                self.position += 1;
+                let Some(ArithmeticToken::Name(name)) = self.tokens.get(self.position).cloned()
+                else {
+                    return Err(self.error());
+                };
+                self.position += 1;
+
+                Ok(Node::Increment {
+                    name,
+                    delta: if op == "++" { 1 } else { -1 },
+                    prefix: true,
+                })
+            }
+            Some(op @ ("!" | "~" | "+" | "-")) => {
+                self.position += 1;
+                let operand = self.unary()?;
+                Ok(Node::Unary(op, Box::new(operand)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Node, ArithmeticError> {
+        let node = self.primary()?;
+
+        if let Node::Variable(name) = &node {
+            if let Some(op @ ("++" | "--")) = self.peek_operator() {
+                self.position += 1;
+
+                return Ok(Node::Increment {
+                    name: name.clone(),
+                    delta: if op == "++" { 1 } else { -1 },
+                    prefix: false,
+                });
+            }
+        }
+
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Result<Node, ArithmeticError> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+
+        match token {
+            Some(ArithmeticToken::Number(n)) => Ok(Node::Number(n)),
+            Some(ArithmeticToken::Name(name)) => Ok(Node::Variable(name)),
+            Some(ArithmeticToken::Operator("(")) => {
+                let node = self.comma()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(ArithmeticToken::Operator("$")) => self.dollar_variable(),
+            _ => Err(self.error()),
+        }
+    }
+
+    /// `$name` and `${name}` are accepted as plain variable references.
+    fn dollar_variable(&mut self) -> Result<Node, ArithmeticError> {
+        let braced = self.peek_operator() == Some("{");
+        if braced {
+            self.position += 1;
+        }
+
+        let Some(ArithmeticToken::Name(name)) = self.tokens.get(self.position).cloned() else {
+            return Err(self.error());
+        };
+        self.position += 1;
+
+        if braced {
+            self.expect("}")?;
+        }
+
+        Ok(Node::Variable(name))
+    }
+}
+
+struct Evaluator<'a> {
+    expression: &'a str,
+    variables: &'a mut Variables,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn variable(&mut self, name: &str) -> Result<i64, ArithmeticError> {
+        let value = self.variables.get(name).unwrap_or_default().to_string();
+
+        if let Ok(n) = value.trim().parse() {
+            return Ok(n);
+        }
+
+        if self.depth > 64 {
+            return Err(ArithmeticError::Recursion(name.to_string()));
+        }
+
+        evaluate_nested(&value, self.variables, self.depth + 1)
+    }
+
+    fn eval(&mut self, node: &Node) -> Result<i64, ArithmeticError> {
+        match node {
+            Node::Number(n) => Ok(*n),
+            Node::Variable(name) => self.variable(name),
+            Node::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+
+                Ok(match *op {
+                    "!" => i64::from(value == 0),
+                    "~" => !value,
+                    "-" => value.wrapping_neg(),
+                    _ => value,
+                })
+            }
+            Node::Binary("&&", left, right) => {
+                Ok(i64::from(self.eval(left)? != 0 && self.eval(right)? != 0))
+            }
+            Node::Binary("||", left, right) => {
+                Ok(i64::from(self.eval(left)? != 0 || self.eval(right)? != 0))
+            }
+            Node::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.apply(op, left, right)
+            }
+            Node::Assign(op, name, value) => {
+                let mut value = self.eval(value)?;
+
+                if let Some(op) = op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    let current = self.variable(name)?;
+                    value = self.apply(op, current, value)?;
+                }
+
+                self.variables.set(name, value.to_string());
+                Ok(value)
+            }
+            Node::Increment {
+                name,
+                delta,
+                prefix,
+            } => {
+                let old = self.variable(name)?;
+                let new = old.wrapping_add(*delta);
+                self.variables.set(name, new.to_string());
+                Ok(if *prefix { new } else { old })
+            }
+            Node::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)? == 0 {
+                    self.eval(otherwise)
+                } else {
+                    self.eval(then)
+                }
+            }
+        }
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, ArithmeticError> {
+        let division_by_zero = || ArithmeticError::DivisionByZero(self.expression.to_string());
+
+        Ok(match op {
+            "," => right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => i64::from(left == right),
+            "!=" => i64::from(left != right),
+            "<" => i64::from(left < right),
+            ">" => i64::from(left > right),
+            "<=" => i64::from(left <= right),
+            ">=" => i64::from(left >= right),
+            "<<" => left.wrapping_shl(u32::try_from(right & 63).unwrap()),
+            ">>" => left.wrapping_shr(u32::try_from(right & 63).unwrap()),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(division_by_zero()),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" => {
+                let Ok(exponent) = u32::try_from(right) else {
+                    return Err(ArithmeticError::NegativeExponent(
+                        self.expression.to_string(),
+                    ));
+                };
+                left.wrapping_pow(exponent)
+            }
+            _ => return Err(ArithmeticError::Syntax(self.expression.to_string())),
+        })
+    }
+}
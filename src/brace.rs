@@ -0,0 +1,231 @@
+use crate::tokenizer::{Segment, Word};
+
+/// A piece of a word as seen by brace expansion: braces and commas only count
+/// when they are unquoted, everything else is carried along untouched.
+#[derive(Clone)]
+enum Unit<'a> {
+    Char(char),
+    Segment(&'a Segment),
+}
+
+/// Expands `{a,b,c}` lists and `{x..y[..step]}` sequences in a word.
+pub fn expand(word: &Word) -> Vec<Word> {
+    let units = to_units(word);
+
+    expand_units(&units)
+        .into_iter()
+        .map(|units| to_word(&units))
+        .collect()
+}
+
+fn to_units(word: &Word) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+
+    for segment in word.segments() {
+        match segment {
+            Segment::Literal(literal) => units.extend(literal.chars().map(Unit::Char)),
+            segment => units.push(Unit::Segment(segment)),
+        }
+    }
+
+    units
+}
+
+fn to_word(units: &[Unit]) -> Word {
+    let mut word = Word::default();
+
+    for unit in units {
+        match unit {
+            Unit::Char(c) => word.push_literal(c.encode_utf8(&mut [0; 4])),
+            Unit::Segment(segment) => word.push_segment((*segment).clone()),
+        }
+    }
+
+    word
+}
+
+fn expand_units<'a>(units: &[Unit<'a>]) -> Vec<Vec<Unit<'a>>> {
+    let mut start = 0;
+
+    while let Some(open) = find_char(units, start, '{') {
+        start = open + 1;
+
+        let Some(close) = matching_brace(units, open) else {
+            continue;
+        };
+
+        let body = &units[open + 1..close];
+        let Some(alternatives) = alternatives(body) else {
+            continue;
+        };
+
+        let prefix = &units[..open];
+        let suffixes = expand_units(&units[close + 1..]);
+        let mut expanded = Vec::new();
+
+        for alternative in alternatives {
+            for suffix in &suffixes {
+                let mut units = prefix.to_vec();
+                units.extend(alternative.iter().cloned());
+                units.extend(suffix.iter().cloned());
+                expanded.push(units);
+            }
+        }
+
+        return expanded;
+    }
+
+    vec![units.to_vec()]
+}
+
+fn find_char(units: &[Unit], start: usize, target: char) -> Option<usize> {
+    units[start..]
+        .iter()
+        .position(|unit| matches!(unit, Unit::Char(c) if *c == target))
+        .map(|i| start + i)
+}
+
+fn matching_brace(units: &[Unit], open: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, unit) in units.iter().enumerate().skip(open) {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The expanded alternatives of the text between a pair of braces, or `None`
+/// if the braces are not a valid brace expansion and stay literal.
+fn alternatives<'a>(body: &[Unit<'a>]) -> Option<Vec<Vec<Unit<'a>>>> {
+    let parts = split_commas(body);
+
+    if parts.len() > 1 {
+        return Some(parts.iter().flat_map(|part| expand_units(part)).collect());
+    }
+
+    let text = body
+        .iter()
+        .map(|unit| match unit {
+            Unit::Char(c) => Some(*c),
+            Unit::Segment(_) => None,
+        })
+        .collect::<Option<String>>()?;
+
+    let sequence = sequence(&text)?;
+
+    Some(
+        sequence
+            .into_iter()
+            .map(|item| item.chars().map(Unit::Char).collect())
+            .collect(),
+    )
+}
+
+fn split_commas<'a, 'b>(body: &'b [Unit<'a>]) -> Vec<&'b [Unit<'a>]> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, unit) in body.iter().enumerate() {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') => depth -= 1,
+            Unit::Char(',') if depth == 0 => {
+                parts.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&body[start..]);
+    parts
+}
+
+/// Generates the items of a `x..y` or `x..y..step` sequence of integers or
+/// single characters.
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let mut parts = text.split("..");
+    let start = parts.next()?;
+    let end = parts.next()?;
+    let step = match parts.next() {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let width = if is_padded(start) || is_padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+
+        return Some(
+            range(first, last, step)
+                .into_iter()
+                .map(|n| {
+                    if n < 0 {
+                        format!(
+                            "-{:0width$}",
+                            n.unsigned_abs(),
+                            width = width.saturating_sub(1)
+                        )
+                    } else {
+                        format!("{n:0width$}")
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    let (mut start_chars, mut end_chars) = (start.chars(), end.chars());
+
+    match (
+        start_chars.next(),
+        start_chars.next(),
+        end_chars.next(),
+        end_chars.next(),
+    ) {
+        (Some(first), None, Some(last), None) => Some(
+            range(
+                i64::from(u32::from(first)),
+                i64::from(u32::from(last)),
+                step,
+            )
+            .into_iter()
+            .filter_map(|n| u32::try_from(n).ok().and_then(char::from_u32))
+            .map(String::from)
+            .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn is_padded(number: &str) -> bool {
+    let digits = number.strip_prefix('-').unwrap_or(number);
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+fn range(first: i64, last: i64, step: u64) -> Vec<i64> {
+    let step = usize::try_from(step).unwrap_or(usize::MAX);
+
+    if first <= last {
+        (first..=last).step_by(step).collect()
+    } else {
+        (last..=first).rev().step_by(step).collect()
+    }
+}
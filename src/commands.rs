@@ -1,23 +1,90 @@
 use std::{
     collections::HashSet,
     env::{self, split_paths},
+    ffi::{CString, OsString},
     fs,
-    io::Write,
-    os::unix::{fs::PermissionsExt, process::CommandExt},
+    io::{self, Read as _, Write},
+    os::{
+        fd::FromRawFd,
+        unix::{
+            ffi::OsStringExt,
+            fs::PermissionsExt,
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
     path::{Path, PathBuf},
-    process::{self, Child, Stdio, exit},
+    process::{self, Child, exit},
+    rc::Rc,
 };
 
-use crate::shell::Shell;
+use crate::{
+    condition,
+    eval::error_message,
+    keymap::Action,
+    options::Options,
+    parser::{Expr, Parser},
+    shell::{Flow, Shell},
+    stdio::{Input, Output},
+    tokenizer::is_name,
+};
+
+const BUILTINS: [&str; 24] = [
+    "echo", "cd", "pwd", "type", "exit", "history", "shopt", "export", "unset", "break",
+    "continue", "read", "return", "shift", "local", "test", "[", "source", ".", "alias", "unalias",
+    "set", "fc", "bind",
+];
+
+/// What executing a command produced: child processes that may still be
+/// running, or the exit status of something the shell ran itself.
+pub enum Process {
+    Child(Child),
+    /// A forked copy of the shell.
+    Forked(libc::pid_t),
+    Exited(i32),
+    Pipeline(Vec<Process>),
+}
+
+impl Process {
+    /// Waits for every process involved and returns the exit status of the
+    /// last one.
+    pub fn wait(self) -> i32 {
+        match self {
+            Process::Child(mut child) => child.wait().map_or(1, |status| {
+                status
+                    .code()
+                    .or_else(|| status.signal().map(|signal| 128 + signal))
+                    .unwrap_or(1)
+            }),
+            Process::Forked(pid) => {
+                let mut status = 0;
+                loop {
+                    // SAFETY: `pid` is a child of this process
+                    let result = unsafe { libc::waitpid(pid, &raw mut status, 0) };
 
-const BUILTINS: [&str; 6] = ["echo", "cd", "pwd", "type", "exit", "history"];
+                    // a resize of the terminal interrupts the wait
+                    if result != -1
+                        || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted
+                    {
+                        break;
+                    }
+                }
+
+                if libc::WIFSIGNALED(status) {
+                    128 + libc::WTERMSIG(status)
+                } else {
+                    libc::WEXITSTATUS(status)
+                }
+            }
+            Process::Exited(status) => status,
+            Process::Pipeline(processes) => {
+                processes.into_iter().fold(0, |_, process| process.wait())
+            }
+        }
+    }
+}
 
 pub trait Executable {
-    fn execute<I, O, E>(&self, shell: &mut Shell, stdin: I, stdout: O, stderr: E) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write;
+    fn execute(&self, shell: &mut Shell, stdin: Input, stdout: Output, stderr: Output) -> Process;
 }
 
 pub enum Command {
@@ -27,16 +94,28 @@ pub enum Command {
     Echo(Echo),
     Exit(Exit),
     History(History),
+    Shopt(Shopt),
+    Export(Export),
+    Unset(Unset),
+    Break(Break),
+    Continue(Continue),
+    Read(Read),
+    Return(Return),
+    Shift(Shift),
+    Local(Local),
+    Test(Test),
+    Source(Source),
+    Alias(Alias),
+    Unalias(Unalias),
+    Set(Set),
+    Fc(Fc),
+    Bind(Bind),
+    Function(Function),
     Binary(Binary),
 }
 
 impl Executable for Command {
-    fn execute<I, O, E>(&self, shell: &mut Shell, stdin: I, stdout: O, stderr: E) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+    fn execute(&self, shell: &mut Shell, stdin: Input, stdout: Output, stderr: Output) -> Process {
         match self {
             Command::Cd(cd) => cd.execute(shell, stdin, stdout, stderr),
             Command::Pwd(pwd) => pwd.execute(shell, stdin, stdout, stderr),
@@ -45,12 +124,39 @@ impl Executable for Command {
             Command::Exit(exit) => exit.execute(shell, stdin, stdout, stderr),
             Command::Binary(binary) => binary.execute(shell, stdin, stdout, stderr),
             Command::History(history) => history.execute(shell, stdin, stdout, stderr),
+            Command::Shopt(shopt) => shopt.execute(shell, stdin, stdout, stderr),
+            Command::Export(export) => export.execute(shell, stdin, stdout, stderr),
+            Command::Unset(unset) => unset.execute(shell, stdin, stdout, stderr),
+            Command::Break(break_) => break_.execute(shell, stdin, stdout, stderr),
+            Command::Continue(continue_) => continue_.execute(shell, stdin, stdout, stderr),
+            Command::Read(read) => read.execute(shell, stdin, stdout, stderr),
+            Command::Return(return_) => return_.execute(shell, stdin, stdout, stderr),
+            Command::Shift(shift) => shift.execute(shell, stdin, stdout, stderr),
+            Command::Local(local) => local.execute(shell, stdin, stdout, stderr),
+            Command::Test(test) => test.execute(shell, stdin, stdout, stderr),
+            Command::Source(source) => source.execute(shell, stdin, stdout, stderr),
+            Command::Alias(alias) => alias.execute(shell, stdin, stdout, stderr),
+            Command::Unalias(unalias) => unalias.execute(shell, stdin, stdout, stderr),
+            Command::Set(set) => set.execute(shell, stdin, stdout, stderr),
+            Command::Fc(fc) => fc.execute(shell, stdin, stdout, stderr),
+            Command::Bind(bind) => bind.execute(shell, stdin, stdout, stderr),
+            Command::Function(function) => function.execute(shell, stdin, stdout, stderr),
         }
     }
 }
 
 impl Command {
-    pub fn new(name: String, args: Vec<String>) -> Self {
+    /// Whether `name` runs an external program rather than a function or a
+    /// builtin.
+    pub fn is_binary(shell: &Shell, name: &str) -> bool {
+        shell.function(name).is_none() && !BUILTINS.contains(&name)
+    }
+
+    pub fn new(shell: &Shell, name: String, args: Vec<String>) -> Self {
+        if let Some(body) = shell.function(&name) {
+            return Self::Function(Function { body, args });
+        }
+
         match name.as_str() {
             "cd" => Self::Cd(Cd {
                 target_directory: args.into_iter().next().map(PathBuf::from),
@@ -58,13 +164,48 @@ impl Command {
 
             "pwd" => Self::Pwd(Pwd),
             "echo" => Self::Echo(Echo { args }),
-            "exit" => Self::Exit(Exit { code: None }),
+            "exit" => Self::Exit(Exit {
+                code: args.first().and_then(|code| code.parse().ok()),
+            }),
             "type" => Self::Type(Type {
                 command: args.into_iter().next().unwrap(),
             }),
             "history" => Self::History(History {
                 argument: HistoryArg::new(&args),
             }),
+            "shopt" => Self::Shopt(Shopt::new(args)),
+            "export" => Self::Export(Export { args }),
+            "unset" => Self::Unset(Unset { names: args }),
+            "break" => Self::Break(Break {
+                count: args
+                    .first()
+                    .and_then(|count| count.parse().ok())
+                    .unwrap_or(1),
+            }),
+            "continue" => Self::Continue(Continue {
+                count: args
+                    .first()
+                    .and_then(|count| count.parse().ok())
+                    .unwrap_or(1),
+            }),
+            "read" => Self::Read(Read::new(args)),
+            "return" => Self::Return(Return {
+                code: args.first().and_then(|code| code.parse().ok()),
+            }),
+            "shift" => Self::Shift(Shift {
+                count: args
+                    .first()
+                    .and_then(|count| count.parse().ok())
+                    .unwrap_or(1),
+            }),
+            "local" => Self::Local(Local { args }),
+            "test" | "[" => Self::Test(Test { name, args }),
+            "source" | "." => Self::Source(Source { name, args }),
+            "alias" => Self::Alias(Alias { args }),
+            "unalias" => Self::Unalias(Unalias { args }),
+            "set" => Self::Set(Set { args }),
+            "fc" => Self::Fc(Fc { args }),
+            "bind" => Self::Bind(Bind { args }),
             _ => Self::Binary(Binary { path: name, args }),
         }
     }
@@ -74,39 +215,51 @@ pub struct Cd {
 }
 
 impl Executable for Cd {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
-        _shell: &mut Shell,
-        _stdin: I,
-        mut stdout: O,
-        _stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
-        let path = match &self.target_directory {
-            Some(path) => path.clone(),
-            None => PathBuf::from("~"),
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        _stderr: Output,
+    ) -> Process {
+        let variables = shell.variables();
+
+        let (path, variable) = match &self.target_directory {
+            Some(path) if path == Path::new("-") => (variables.get("OLDPWD"), "OLDPWD"),
+            Some(path) => (path.to_str(), ""),
+            None => (variables.get("HOME"), "HOME"),
         };
 
-        if path == Path::new("~") {
-            let home_dir = env::home_dir().unwrap();
-            env::set_current_dir(&home_dir).unwrap();
-            return None;
-        }
+        let Some(path) = path.map(PathBuf::from) else {
+            writeln!(stdout, "cd: {variable} not set").unwrap();
+            return Process::Exited(1);
+        };
 
         let current_directory = env::current_dir().unwrap();
+        let mut status = 0;
 
         match current_directory.join(&path).canonicalize() {
-            Ok(new_dir) => std::env::set_current_dir(&new_dir).unwrap(),
+            Ok(new_dir) => {
+                std::env::set_current_dir(&new_dir).unwrap();
+
+                let variables = shell.variables_mut();
+                let old_dir = variables
+                    .get("PWD")
+                    .map_or_else(|| current_directory.display().to_string(), String::from);
+                variables.set("OLDPWD", old_dir);
+                variables.set("PWD", new_dir.display().to_string());
+
+                if self.target_directory.as_deref() == Some(Path::new("-")) {
+                    writeln!(stdout, "{}", new_dir.display()).unwrap();
+                }
+            }
             Err(_e) => {
                 writeln!(stdout, "cd: {}: No such file or directory", path.display()).unwrap();
+                status = 1;
             }
         }
 
-        None
+        Process::Exited(status)
     }
 }
 
@@ -115,29 +268,37 @@ pub struct Type {
 }
 
 impl Executable for Type {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
-        _shell: &mut Shell,
-        _stdin: I,
-        mut stdout: O,
-        mut stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        if let Some(value) = shell.aliases().get(&self.command) {
+            writeln!(stdout, "{} is aliased to `{value}'", self.command).unwrap();
+            return Process::Exited(0);
+        }
+
+        if shell.function(&self.command).is_some() {
+            writeln!(stdout, "{} is a function", self.command).unwrap();
+            return Process::Exited(0);
+        }
+
         if BUILTINS.contains(&self.command.as_str()) {
             writeln!(stdout, "{} is a shell builtin", self.command).unwrap();
-            return None;
+            return Process::Exited(0);
         }
 
-        match find_path(&self.command) {
-            Some(file) => writeln!(stdout, "{} is {}", self.command, file.display()).unwrap(),
-            None => writeln!(stderr, "{}: not found", self.command).unwrap(),
-        }
+        let status = if let Some(file) = find_path(shell, &self.command) {
+            writeln!(stdout, "{} is {}", self.command, file.display()).unwrap();
+            0
+        } else {
+            writeln!(stderr, "{}: not found", self.command).unwrap();
+            1
+        };
 
-        None
+        Process::Exited(status)
     }
 }
 
@@ -146,38 +307,28 @@ pub struct Echo {
 }
 
 impl Executable for Echo {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
         _shell: &mut Shell,
-        _stdin: I,
-        mut stdout: O,
-        _stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+        _stdin: Input,
+        mut stdout: Output,
+        _stderr: Output,
+    ) -> Process {
         writeln!(stdout, "{}", self.args.join(" ")).unwrap();
-        None
+        Process::Exited(0)
     }
 }
 
 pub struct Pwd;
 
 impl Executable for Pwd {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
         _shell: &mut Shell,
-        _stdin: I,
-        mut stdout: O,
-        _stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+        _stdin: Input,
+        mut stdout: Output,
+        _stderr: Output,
+    ) -> Process {
         // TODO:
         // if !args.is_empty() {
         //     writeln!(output.stderr, "pwd: too many arguments");
@@ -186,7 +337,7 @@ impl Executable for Pwd {
 
         let current_directory = env::current_dir().unwrap();
         writeln!(stdout, "{}", current_directory.display()).unwrap();
-        None
+        Process::Exited(0)
     }
 }
 
@@ -200,50 +351,58 @@ pub struct Binary {
 }
 
 impl Executable for Binary {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
-        _shell: &mut Shell,
-        stdin: I,
-        stdout: O,
-        mut stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
-        let Some(path) = find_path(&self.path) else {
+        shell: &mut Shell,
+        stdin: Input,
+        stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        // a path is run as it is, so that the system says what is wrong
+        // with it
+        let path = if self.path.contains('/') {
+            PathBuf::from(&self.path)
+        } else if let Some(path) = find_path(shell, &self.path) {
+            path
+        } else {
             writeln!(stderr, "{}: command not found", self.path).unwrap();
-            return None;
+            return Process::Exited(127);
         };
 
         let mut command = process::Command::new(&path);
-        command.arg0(path.file_name().unwrap());
+        command.arg0(&self.path);
         command.args(&self.args);
+        command.env_clear();
+        command.envs(shell.variables().exported());
         command.stdin(stdin);
         command.stdout(stdout);
-        command.stderr(stderr);
+        command.stderr(stderr.try_clone().unwrap());
 
-        Some(command.spawn().unwrap())
+        match command.spawn() {
+            Ok(child) => Process::Child(child),
+            Err(e) => {
+                writeln!(stderr, "{}: {}", self.path, error_message(&e)).unwrap();
+                Process::Exited(if e.kind() == io::ErrorKind::NotFound {
+                    127
+                } else {
+                    126
+                })
+            }
+        }
     }
 }
 
 impl Executable for Exit {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
         shell: &mut Shell,
-        _stdin: I,
-        _stdout: O,
-        _stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+        _stdin: Input,
+        _stdout: Output,
+        _stderr: Output,
+    ) -> Process {
         shell.write_history_file();
 
-        exit(self.code.unwrap_or_default());
+        exit(self.code.unwrap_or(shell.status()));
     }
 }
 
@@ -275,18 +434,13 @@ impl HistoryArg {
 }
 
 impl Executable for History {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
         shell: &mut Shell,
-        _stdin: I,
-        mut stdout: O,
-        _stderr: E,
-    ) -> Option<Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + Write,
-        E: Into<Stdio> + Write,
-    {
+        _stdin: Input,
+        mut stdout: Output,
+        _stderr: Output,
+    ) -> Process {
         match &self.argument {
             HistoryArg::None => {
                 let history = shell.history();
@@ -295,37 +449,1062 @@ impl Executable for History {
                     writeln!(stdout, "  {} {}", i + 1, command).unwrap();
                 }
 
-                None
+                Process::Exited(0)
             }
             HistoryArg::Amount(n) => {
                 let history = shell.history();
 
-                let skipped = history.len() - n;
+                let skipped = history.len().saturating_sub(*n);
 
                 for (i, command) in history.into_iter().enumerate().skip(skipped) {
                     writeln!(stdout, "  {} {}", i + 1, command).unwrap();
                 }
 
-                None
+                Process::Exited(0)
             }
             HistoryArg::Read(path_buf) => {
                 let history = shell.history();
                 history.read_from_file(path_buf.clone());
-                None
+                Process::Exited(0)
             }
             HistoryArg::Write(path_buf) => {
-                shell.history().write_to_file(path_buf.clone());
-                None
+                let directories = shell.options().histcwd;
+                shell.history().write_to_file(path_buf.clone(), directories);
+                Process::Exited(0)
             }
             HistoryArg::Append(path_buf) => {
-                shell.history().append_to_file(path_buf.clone());
-                None
+                let directories = shell.options().histcwd;
+                shell
+                    .history()
+                    .append_to_file(path_buf.clone(), directories);
+                Process::Exited(0)
+            }
+        }
+    }
+}
+
+pub struct Shopt {
+    set: Option<bool>,
+    names: Vec<String>,
+}
+
+impl Shopt {
+    pub fn new(args: Vec<String>) -> Self {
+        let mut args = args.into_iter().peekable();
+
+        let set = match args.peek().map(String::as_str) {
+            Some("-s") => Some(true),
+            Some("-u") => Some(false),
+            _ => None,
+        };
+
+        if set.is_some() {
+            args.next();
+        }
+
+        Self {
+            set,
+            names: args.collect(),
+        }
+    }
+}
+
+impl Executable for Shopt {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let names: Vec<&str> = if self.names.is_empty() {
+            Options::NAMES.to_vec()
+        } else {
+            self.names.iter().map(String::as_str).collect()
+        };
+
+        let mut status = 0;
+
+        for name in names {
+            let Some(option) = shell.options_mut().get_mut(name) else {
+                writeln!(stderr, "shopt: {name}: invalid shell option name").unwrap();
+                status = 1;
+                continue;
+            };
+
+            if let Some(value) = self.set {
+                *option = value;
+            } else {
+                let state = if *option { "on" } else { "off" };
+                writeln!(stdout, "{name:<15}\t{state}").unwrap();
+            }
+        }
+
+        Process::Exited(status)
+    }
+}
+
+pub struct Export {
+    args: Vec<String>,
+}
+
+impl Executable for Export {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        if self.args.is_empty() {
+            let mut exported: Vec<_> = shell.variables().exported().collect();
+            exported.sort_unstable();
+
+            for (name, value) in exported {
+                writeln!(stdout, "declare -x {name}=\"{value}\"").unwrap();
+            }
+
+            return Process::Exited(0);
+        }
+
+        let mut status = 0;
+
+        for arg in &self.args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_name(name) {
+                writeln!(stderr, "export: `{arg}': not a valid identifier").unwrap();
+                status = 1;
+                continue;
+            }
+
+            if let Some(value) = value {
+                shell.variables_mut().set(name, value.to_string());
+            }
+            shell.variables_mut().export(name);
+        }
+
+        Process::Exited(status)
+    }
+}
+
+pub struct Unset {
+    names: Vec<String>,
+}
+
+impl Executable for Unset {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        _stderr: Output,
+    ) -> Process {
+        for name in &self.names {
+            shell.variables_mut().unset(name);
+        }
+
+        Process::Exited(0)
+    }
+}
+
+pub struct Break {
+    count: usize,
+}
+
+impl Executable for Break {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        stderr: Output,
+    ) -> Process {
+        loop_control(shell, "break", self.count, Flow::Break, stderr)
+    }
+}
+
+pub struct Continue {
+    count: usize,
+}
+
+impl Executable for Continue {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        stderr: Output,
+    ) -> Process {
+        loop_control(shell, "continue", self.count, Flow::Continue, stderr)
+    }
+}
+
+fn loop_control(
+    shell: &mut Shell,
+    name: &str,
+    count: usize,
+    flow: fn(usize) -> Flow,
+    mut stderr: Output,
+) -> Process {
+    if shell.loop_depth() == 0 {
+        writeln!(
+            stderr,
+            "{name}: only meaningful in a `for', `while', or `until' loop"
+        )
+        .unwrap();
+        return Process::Exited(0);
+    }
+
+    if count == 0 {
+        writeln!(stderr, "{name}: {count}: loop count out of range").unwrap();
+        return Process::Exited(1);
+    }
+
+    shell.set_flow(flow(count.min(shell.loop_depth())));
+    Process::Exited(0)
+}
+
+pub struct Read {
+    raw: bool,
+    names: Vec<String>,
+}
+
+impl Read {
+    pub fn new(args: Vec<String>) -> Self {
+        let mut args = args.into_iter().peekable();
+        let raw = args.next_if(|arg| arg == "-r").is_some();
+
+        let mut names: Vec<String> = args.collect();
+        if names.is_empty() {
+            names.push(String::from("REPLY"));
+        }
+
+        Self { raw, names }
+    }
+
+    /// Reads a line one byte at a time, so that the rest of the input is left
+    /// for whatever reads it next. Returns the line, with every character
+    /// marked if it was escaped, and whether the line ended with a newline.
+    fn read_line(&self, stdin: &mut Input) -> (Vec<(char, bool)>, bool) {
+        let mut bytes = Vec::new();
+        let mut byte = [0];
+        let mut complete = false;
+        let mut escaped = false;
+
+        while stdin.read_exact(&mut byte).is_ok() {
+            match byte[0] {
+                // an escaped newline continues the line
+                b'\n' if escaped => {
+                    bytes.pop();
+                    escaped = false;
+                }
+                b'\n' => {
+                    complete = true;
+                    break;
+                }
+                byte => {
+                    escaped = !self.raw && byte == b'\\' && !escaped;
+                    bytes.push(byte);
+                }
+            }
+        }
+
+        let line = String::from_utf8_lossy(&bytes);
+        let mut chars = Vec::new();
+        let mut escaped = false;
+
+        for c in line.chars() {
+            if c == '\\' && !self.raw && !escaped {
+                escaped = true;
+                continue;
+            }
+            chars.push((c, escaped));
+            escaped = false;
+        }
+
+        (chars, complete)
+    }
+}
+
+impl Executable for Read {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        mut stdin: Input,
+        _stdout: Output,
+        _stderr: Output,
+    ) -> Process {
+        let (line, complete) = self.read_line(&mut stdin);
+        let ifs = shell.variables().get("IFS").unwrap_or(" \t\n").to_string();
+        let is_ifs = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+        let is_ifs_whitespace = |piece: &(char, bool)| is_ifs(piece) && " \t\n".contains(piece.0);
+
+        let mut rest = line.as_slice();
+        while rest.first().is_some_and(is_ifs_whitespace) {
+            rest = &rest[1..];
+        }
+        while rest.last().is_some_and(is_ifs_whitespace) {
+            rest = &rest[..rest.len() - 1];
+        }
+
+        for (i, name) in self.names.iter().enumerate() {
+            let value: String;
+
+            if i == self.names.len() - 1 {
+                value = rest.iter().map(|&(c, _)| c).collect();
+                rest = &[];
+            } else {
+                let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+                value = rest[..end].iter().map(|&(c, _)| c).collect();
+                rest = &rest[end..];
+
+                // skip the delimiter: IFS whitespace around at most one other
+                // IFS character
+                while rest.first().is_some_and(is_ifs_whitespace) {
+                    rest = &rest[1..];
+                }
+                if rest.first().is_some_and(is_ifs) {
+                    rest = &rest[1..];
+                    while rest.first().is_some_and(is_ifs_whitespace) {
+                        rest = &rest[1..];
+                    }
+                }
+            }
+
+            shell.variables_mut().set(name, value);
+        }
+
+        Process::Exited(i32::from(!complete))
+    }
+}
+
+pub struct Return {
+    code: Option<i32>,
+}
+
+impl Executable for Return {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        if shell.call_depth() == 0 {
+            writeln!(
+                stderr,
+                "return: can only `return' from a function or sourced script"
+            )
+            .unwrap();
+            return Process::Exited(1);
+        }
+
+        shell.set_flow(Flow::Return);
+        Process::Exited(self.code.unwrap_or(shell.status()))
+    }
+}
+
+pub struct Shift {
+    count: usize,
+}
+
+impl Executable for Shift {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        _stderr: Output,
+    ) -> Process {
+        let Some(rest) = shell.positional().get(self.count..) else {
+            return Process::Exited(1);
+        };
+
+        let rest = rest.to_vec();
+        shell.set_positional(rest);
+        Process::Exited(0)
+    }
+}
+
+pub struct Local {
+    args: Vec<String>,
+}
+
+impl Executable for Local {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let mut status = 0;
+
+        for arg in &self.args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_name(name) {
+                writeln!(stderr, "local: `{arg}': not a valid identifier").unwrap();
+                status = 1;
+                continue;
+            }
+
+            if !shell.variables_mut().make_local(name) {
+                writeln!(stderr, "local: can only be used in a function").unwrap();
+                return Process::Exited(1);
+            }
+
+            shell.variables_mut().unset(name);
+            if let Some(value) = value {
+                shell.variables_mut().set(name, value.to_string());
+            }
+        }
+
+        Process::Exited(status)
+    }
+}
+
+pub struct Test {
+    name: String,
+    args: Vec<String>,
+}
+
+impl Executable for Test {
+    fn execute(
+        &self,
+        _shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let mut args = self.args.as_slice();
+
+        if self.name == "[" {
+            let Some((_, rest)) = args.split_last().filter(|(last, _)| *last == "]") else {
+                writeln!(stderr, "[: missing `]'").unwrap();
+                return Process::Exited(2);
+            };
+            args = rest;
+        }
+
+        match condition::test(args) {
+            Ok(result) => Process::Exited(i32::from(!result)),
+            Err(e) => {
+                writeln!(stderr, "{}: {e}", self.name).unwrap();
+                Process::Exited(2)
+            }
+        }
+    }
+}
+
+/// Runs the commands of a file in the current shell, so that the variables,
+/// functions and directory it changes stay changed.
+pub struct Source {
+    name: String,
+    args: Vec<String>,
+}
+
+impl Executable for Source {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        stdin: Input,
+        stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let Some((file, args)) = self.args.split_first() else {
+            writeln!(stderr, "{}: filename argument required", self.name).unwrap();
+            return Process::Exited(2);
+        };
+
+        // a name without a slash is looked up in `$PATH`, then in the
+        // current directory
+        let path = if file.contains('/') {
+            PathBuf::from(file)
+        } else {
+            find_source_file(shell, file).unwrap_or_else(|| PathBuf::from(file))
+        };
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                writeln!(stderr, "{}: {file}: {}", self.name, error_message(&e)).unwrap();
+                return Process::Exited(1);
+            }
+        };
+
+        // arguments after the file name replace the positional parameters
+        // while it runs
+        let positional = (!args.is_empty()).then(|| shell.set_positional(args.to_vec()));
+        let call_depth = shell.call_depth();
+        shell.set_call_depth(call_depth + 1);
+
+        let result = shell.run_source(&source, &stdin, &stdout, &stderr);
+
+        if shell.flow() == Some(Flow::Return) {
+            shell.take_flow();
+        }
+
+        shell.set_call_depth(call_depth);
+        if let Some(positional) = positional {
+            shell.set_positional(positional);
+        }
+
+        match result {
+            Ok(status) => Process::Exited(status),
+            Err(e) => {
+                writeln!(stderr, "{file}: {e}").unwrap();
+                Process::Exited(2)
+            }
+        }
+    }
+}
+
+pub struct Alias {
+    args: Vec<String>,
+}
+
+impl Executable for Alias {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        if self.args.is_empty() {
+            let mut aliases: Vec<_> = shell.aliases().iter().collect();
+            aliases.sort();
+
+            for (name, value) in aliases {
+                writeln!(stdout, "alias {name}={}", single_quote(value)).unwrap();
+            }
+            return Process::Exited(0);
+        }
+
+        let mut status = 0;
+
+        for arg in &self.args {
+            if let Some((name, value)) = arg.split_once('=') {
+                if name.is_empty() || name.contains(['/', '$', '`', '\'', '"']) {
+                    writeln!(stderr, "alias: `{name}': invalid alias name").unwrap();
+                    status = 1;
+                    continue;
+                }
+
+                shell
+                    .aliases_mut()
+                    .insert(name.to_string(), value.to_string());
+            } else if let Some(value) = shell.aliases().get(arg) {
+                writeln!(stdout, "alias {arg}={}", single_quote(value)).unwrap();
+            } else {
+                writeln!(stderr, "alias: {arg}: not found").unwrap();
+                status = 1;
+            }
+        }
+
+        Process::Exited(status)
+    }
+}
+
+pub struct Unalias {
+    args: Vec<String>,
+}
+
+impl Executable for Unalias {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        if self.args.first().is_some_and(|arg| arg == "-a") {
+            shell.aliases_mut().clear();
+            return Process::Exited(0);
+        }
+
+        if self.args.is_empty() {
+            writeln!(stderr, "unalias: usage: unalias [-a] name [name ...]").unwrap();
+            return Process::Exited(2);
+        }
+
+        let mut status = 0;
+
+        for name in &self.args {
+            if shell.aliases_mut().remove(name).is_none() {
+                writeln!(stderr, "unalias: {name}: not found").unwrap();
+                status = 1;
+            }
+        }
+
+        Process::Exited(status)
+    }
+}
+
+/// Turns shell options on with `-` and off with `+`, or replaces the
+/// positional parameters with the arguments after `--`.
+pub struct Set {
+    args: Vec<String>,
+}
+
+impl Executable for Set {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        _stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let mut args = self.args.iter();
+
+        while let Some(arg) = args.next() {
+            let enable = arg.starts_with('-');
+
+            match arg.as_str() {
+                "--" => {
+                    shell.set_positional(args.cloned().collect());
+                    break;
+                }
+                "-x" | "+x" => shell.options_mut().xtrace = enable,
+                "-o" | "+o" => match args.next().map(String::as_str) {
+                    Some("xtrace") => shell.options_mut().xtrace = enable,
+                    Some(name) => {
+                        writeln!(stderr, "set: {name}: invalid option name").unwrap();
+                        return Process::Exited(2);
+                    }
+                    None => {
+                        writeln!(stderr, "set: {arg}: option requires an argument").unwrap();
+                        return Process::Exited(2);
+                    }
+                },
+                arg => {
+                    writeln!(stderr, "set: {arg}: invalid option").unwrap();
+                    return Process::Exited(2);
+                }
+            }
+        }
+
+        Process::Exited(0)
+    }
+}
+
+/// Lists, edits or re-executes commands from the history. The `fc` command
+/// typed at the prompt is left out, and replaced in the history by the
+/// commands it runs.
+pub struct Fc {
+    args: Vec<String>,
+}
+
+#[derive(Default)]
+enum FcMode {
+    /// Edit the commands in the given editor, then run them.
+    Edit(Option<String>),
+    #[default]
+    List,
+    Substitute,
+}
+
+#[derive(Default)]
+struct FcOptions {
+    mode: FcMode,
+    unnumbered: bool,
+    reverse: bool,
+    operands: Vec<String>,
+}
+
+impl Fc {
+    fn options(&self) -> Result<FcOptions, String> {
+        let mut options = FcOptions {
+            mode: FcMode::Edit(None),
+            ..FcOptions::default()
+        };
+        let mut list = false;
+        let mut args = self.args.iter();
+
+        while let Some(arg) = args.next() {
+            // a negative number is an offset into the history, not an option
+            let is_option = arg.len() > 1
+                && arg.starts_with('-')
+                && !arg[1..].chars().all(|c| c.is_ascii_digit());
+
+            if arg == "--" {
+                options.operands.extend(args.by_ref().cloned());
+                break;
+            } else if !is_option {
+                options.operands.push(arg.clone());
+                continue;
+            }
+
+            for flag in arg[1..].chars() {
+                match flag {
+                    'l' => list = true,
+                    'n' => options.unnumbered = true,
+                    'r' => options.reverse = true,
+                    's' => options.mode = FcMode::Substitute,
+                    'e' => {
+                        let editor = args
+                            .next()
+                            .ok_or("-e: option requires an argument".to_string())?;
+                        // `-e -` runs the command again without editing it
+                        if editor == "-" {
+                            options.mode = FcMode::Substitute;
+                        } else if let FcMode::Edit(_) = options.mode {
+                            options.mode = FcMode::Edit(Some(editor.clone()));
+                        }
+                    }
+                    flag => return Err(format!("-{flag}: invalid option")),
+                }
+            }
+        }
+
+        if list {
+            options.mode = FcMode::List;
+        }
+
+        Ok(options)
+    }
+
+    fn list(
+        options: &FcOptions,
+        commands: &[String],
+        stdout: &mut Output,
+    ) -> Result<(), &'static str> {
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        let first = match options.operands.first() {
+            Some(spec) => history_index(commands, spec)?,
+            None => commands.len().saturating_sub(16),
+        };
+        let last = match options.operands.get(1) {
+            Some(spec) => history_index(commands, spec)?,
+            None => commands.len() - 1,
+        };
+
+        let mut indices: Vec<usize> = (first.min(last)..=first.max(last)).collect();
+        if (first > last) != options.reverse {
+            indices.reverse();
+        }
+
+        for i in indices {
+            if options.unnumbered {
+                writeln!(stdout, "\t{}", commands[i]).unwrap();
+            } else {
+                writeln!(stdout, "{}\t{}", i + 1, commands[i]).unwrap();
             }
         }
+
+        Ok(())
+    }
+
+    /// Applies the `old=new` operand, if any, to the command chosen by the
+    /// next operand.
+    fn substitute(options: &FcOptions, commands: &[String]) -> Result<String, &'static str> {
+        let (replacement, spec) = match options.operands.first() {
+            Some(operand) if operand.contains('=') => {
+                (operand.split_once('='), options.operands.get(1))
+            }
+            spec => (None, spec),
+        };
+
+        let index = match spec {
+            Some(spec) => history_index(commands, spec)?,
+            None => commands.len().checked_sub(1).ok_or(OUT_OF_RANGE)?,
+        };
+
+        Ok(match replacement {
+            Some((old, new)) if !old.is_empty() => commands[index].replace(old, new),
+            _ => commands[index].clone(),
+        })
+    }
+
+    fn edit(
+        shell: &mut Shell,
+        editor: Option<&str>,
+        options: &FcOptions,
+        commands: &[String],
+    ) -> Result<Option<String>, String> {
+        let first = match options.operands.first() {
+            Some(spec) => history_index(commands, spec)?,
+            None => commands.len().checked_sub(1).ok_or(OUT_OF_RANGE)?,
+        };
+        let last = match options.operands.get(1) {
+            Some(spec) => history_index(commands, spec)?,
+            None => first,
+        };
+
+        let mut selected: Vec<&str> = commands[first.min(last)..=first.max(last)]
+            .iter()
+            .map(String::as_str)
+            .collect();
+        if (first > last) != options.reverse {
+            selected.reverse();
+        }
+
+        let editor = editor
+            .or_else(|| shell.variables().get("FCEDIT"))
+            .or_else(|| shell.variables().get("EDITOR"))
+            .unwrap_or("vi")
+            .to_string();
+
+        run_editor(shell, &editor, &(selected.join("\n") + "\n")).map_err(|e| error_message(&e))
     }
 }
 
-pub fn get_commands() -> HashSet<String> {
+impl Executable for Fc {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let options = match self.options() {
+            Ok(options) => options,
+            Err(e) => {
+                writeln!(stderr, "fc: {e}").unwrap();
+                return Process::Exited(2);
+            }
+        };
+
+        // the entry of the command line `fc` was typed on is left out, but not
+        // when a function or a sourced file runs it
+        let line = shell.command_line().to_string();
+        let mut commands: Vec<String> = shell.history().into_iter().cloned().collect();
+        let own_line = shell.call_depth() == 0
+            && !line.is_empty()
+            && commands.last().is_some_and(|last| *last == line);
+        if own_line {
+            commands.pop();
+        }
+
+        let source = match &options.mode {
+            FcMode::List => Self::list(&options, &commands, &mut stdout)
+                .map(|()| None)
+                .map_err(String::from),
+            FcMode::Substitute => Self::substitute(&options, &commands)
+                .map(Some)
+                .map_err(String::from),
+            FcMode::Edit(editor) => Self::edit(shell, editor.as_deref(), &options, &commands),
+        };
+
+        let source = match source {
+            Ok(Some(source)) => source.trim_end_matches('\n').to_string(),
+            Ok(None) => return Process::Exited(0),
+            Err(e) => {
+                writeln!(stderr, "fc: {e}").unwrap();
+                return Process::Exited(1);
+            }
+        };
+
+        if source.is_empty() {
+            return Process::Exited(0);
+        }
+
+        writeln!(stdout, "{source}").unwrap();
+        if own_line {
+            shell.history().replace_last(source.clone());
+        } else {
+            shell.history().add(source.clone());
+        }
+
+        match Parser::parse_source(&source, shell.aliases()) {
+            Ok(Some(ast)) => Process::Exited(ast.execute(shell, stdin, stdout, stderr).wait()),
+            Ok(None) => Process::Exited(0),
+            Err(e) => {
+                writeln!(stderr, "fc: {e}").unwrap();
+                Process::Exited(2)
+            }
+        }
+    }
+}
+
+const OUT_OF_RANGE: &str = "history specification out of range";
+
+/// Finds a command in the history by its number, by an offset back from the
+/// end when negative, or as the last one starting with `spec`.
+fn history_index(commands: &[String], spec: &str) -> Result<usize, &'static str> {
+    let index = match spec.parse::<i64>() {
+        Ok(number) if number > 0 => usize::try_from(number)
+            .map_or(commands.len(), |number| number.min(commands.len()))
+            .checked_sub(1),
+        // zero is the last command, and offsets reaching past the start
+        // stop at the first one
+        Ok(number) => {
+            let offset = usize::try_from(number.unsigned_abs()).unwrap_or(usize::MAX);
+            commands
+                .len()
+                .checked_sub(1)
+                .map(|last| commands.len().saturating_sub(offset).min(last))
+        }
+        Err(_) => commands
+            .iter()
+            .rposition(|command| command.starts_with(spec)),
+    };
+
+    index.ok_or(OUT_OF_RANGE)
+}
+
+/// Lets the user edit `text` in `editor`, returning what they saved unless
+/// the editor failed.
+pub fn run_editor(shell: &mut Shell, editor: &str, text: &str) -> io::Result<Option<String>> {
+    let (mut file, path) = temp_file()?;
+    file.write_all(text.as_bytes())?;
+    drop(file);
+
+    let status = shell.run_script(&format!(
+        "{editor} {}",
+        single_quote(&path.display().to_string())
+    ));
+    let edited = fs::read_to_string(&path);
+    fs::remove_file(&path).ok();
+
+    if status != 0 {
+        return Ok(None);
+    }
+    edited.map(Some)
+}
+
+/// Creates a new file in the temporary directory that only this user can
+/// read, under a name no other file had, so nobody can make it point
+/// elsewhere.
+fn temp_file() -> io::Result<(fs::File, PathBuf)> {
+    let template = env::temp_dir().join("shell-edit-XXXXXX.sh");
+    let mut template = CString::new(template.into_os_string().into_vec())?.into_bytes_with_nul();
+
+    // SAFETY: the template is a writable C string ending in the 3-byte suffix
+    let fd = unsafe { libc::mkstemps(template.as_mut_ptr().cast(), 3) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    template.pop();
+    // SAFETY: `fd` was just opened and nothing else owns it
+    let file = unsafe { fs::File::from_raw_fd(fd) };
+    Ok((file, PathBuf::from(OsString::from_vec(template))))
+}
+
+/// Changes the key bindings of the line editor, taking lines in inputrc
+/// format, or lists them.
+pub struct Bind {
+    args: Vec<String>,
+}
+
+impl Executable for Bind {
+    fn execute(
+        &self,
+        shell: &mut Shell,
+        _stdin: Input,
+        mut stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
+        let mut args = self.args.iter();
+        let mut status = 0;
+
+        while let Some(arg) = args.next() {
+            let result = match arg.as_str() {
+                "-l" => {
+                    for name in Action::names() {
+                        writeln!(stdout, "{name}").unwrap();
+                    }
+                    Ok(())
+                }
+                "-p" => {
+                    for line in shell.keymap().bindings() {
+                        writeln!(stdout, "{line}").unwrap();
+                    }
+                    Ok(())
+                }
+                "-v" => {
+                    for line in shell.keymap().variables() {
+                        writeln!(stdout, "{line}").unwrap();
+                    }
+                    Ok(())
+                }
+                option @ ("-f" | "-r") => {
+                    let Some(operand) = args.next() else {
+                        writeln!(stderr, "bind: {option}: option requires an argument").unwrap();
+                        return Process::Exited(2);
+                    };
+
+                    if option == "-r" {
+                        shell.keymap_mut().unbind(operand)
+                    } else {
+                        match shell.keymap_mut().read_file(Path::new(operand)) {
+                            Ok(errors) => {
+                                for e in errors {
+                                    writeln!(stderr, "bind: {e}").unwrap();
+                                }
+                                Ok(())
+                            }
+                            Err(e) => {
+                                writeln!(stderr, "bind: {operand}: {}", error_message(&e)).unwrap();
+                                status = 1;
+                                Ok(())
+                            }
+                        }
+                    }
+                }
+                option if option.starts_with('-') && option.len() > 1 => {
+                    writeln!(stderr, "bind: {option}: invalid option").unwrap();
+                    return Process::Exited(2);
+                }
+                line => shell.keymap_mut().parse_line(line),
+            };
+
+            if let Err(e) = result {
+                writeln!(stderr, "bind: {e}").unwrap();
+                status = 1;
+            }
+        }
+
+        Process::Exited(status)
+    }
+}
+
+/// Quotes `value` so that the shell reads it back unchanged.
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A call to a shell function, which runs its body with the arguments as
+/// positional parameters and a new scope for local variables.
+pub struct Function {
+    body: Rc<Expr>,
+    args: Vec<String>,
+}
+
+impl Executable for Function {
+    fn execute(&self, shell: &mut Shell, stdin: Input, stdout: Output, stderr: Output) -> Process {
+        let positional = shell.set_positional(self.args.clone());
+        let call_depth = shell.call_depth();
+        shell.set_call_depth(call_depth + 1);
+        shell.variables_mut().push_scope();
+
+        let status = self.body.execute(shell, stdin, stdout, stderr).wait();
+
+        if shell.flow() == Some(Flow::Return) {
+            shell.take_flow();
+        }
+
+        shell.variables_mut().pop_scope();
+        shell.set_call_depth(call_depth);
+        shell.set_positional(positional);
+
+        Process::Exited(status)
+    }
+}
+
+pub fn get_commands(shell: &Shell) -> HashSet<String> {
     let mut commands: HashSet<String> = HashSet::new();
 
     // add builtin commands
@@ -333,8 +1512,18 @@ pub fn get_commands() -> HashSet<String> {
         commands.insert(builtin.to_string());
     }
 
+    // add aliases
+    for alias in shell.aliases().keys() {
+        commands.insert(alias.clone());
+    }
+
+    // add functions
+    for function in shell.functions() {
+        commands.insert(function.to_string());
+    }
+
     // add binaries
-    let path = env::var("PATH").unwrap();
+    let path = shell.variables().get("PATH").unwrap_or_default();
 
     for dir in split_paths(&path) {
         let dir = fs::read_dir(dir).unwrap();
@@ -348,8 +1537,35 @@ pub fn get_commands() -> HashSet<String> {
     commands
 }
 
-pub fn find_path(command_name: &str) -> Option<PathBuf> {
-    let path = env::var("PATH").unwrap();
+/// Looks for a file to source in the directories of `$PATH`, which unlike a
+/// command need not be executable.
+fn find_source_file(shell: &Shell, file_name: &str) -> Option<PathBuf> {
+    let path = shell.variables().get("PATH").unwrap_or_default();
+
+    split_paths(&path)
+        .map(|dir| dir.join(file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Whether `name` runs something: an alias, a function, a builtin or an
+/// executable file.
+pub fn command_exists(shell: &Shell, name: &str) -> bool {
+    shell.aliases().contains_key(name)
+        || shell.function(name).is_some()
+        || BUILTINS.contains(&name)
+        || find_path(shell, name).is_some()
+}
+
+/// The executable file a command name runs: the name itself if it has a
+/// slash, or else the first match in `$PATH`.
+pub fn find_path(shell: &Shell, command_name: &str) -> Option<PathBuf> {
+    if command_name.contains('/') {
+        let is_executable = fs::metadata(command_name)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0);
+        return is_executable.then(|| PathBuf::from(command_name));
+    }
+
+    let path = shell.variables().get("PATH").unwrap_or_default();
 
     for mut dir in split_paths(&path) {
         dir.push(command_name);
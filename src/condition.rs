@@ -0,0 +1,279 @@
+use std::{
+    ffi::CString,
+    fs,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::{
+    expand::{self, ExpansionError, expand_to_pattern, expand_to_regex, expand_to_string},
+    glob::Pattern,
+    parser::{Condition, is_binary_operator, is_unary_operator},
+    shell::Shell,
+};
+
+#[derive(Debug, Error)]
+pub enum ConditionError {
+    #[error("{0}: integer expression expected")]
+    Integer(String),
+    #[error("{0}: unary operator expected")]
+    UnaryOperator(String),
+    #[error("`)' expected")]
+    ClosingParen,
+    #[error("too many arguments")]
+    TooManyArguments,
+    #[error("{0}: invalid regular expression")]
+    Regex(String),
+    #[error(transparent)]
+    Expansion(#[from] ExpansionError),
+}
+
+/// Evaluates the arguments of `test`, or of `[` without the closing `]`.
+pub fn test(args: &[String]) -> Result<bool, ConditionError> {
+    let mut parser = TestParser { args, position: 0 };
+
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    let result = parser.or()?;
+
+    if parser.position < args.len() {
+        return Err(ConditionError::TooManyArguments);
+    }
+
+    Ok(result)
+}
+
+/// Recursive descent over the arguments of `test`, where `-o` binds looser
+/// than `-a`, which binds looser than `!`.
+struct TestParser<'a> {
+    args: &'a [String],
+    position: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn peek(&self, n: usize) -> Option<&'a str> {
+        self.args.get(self.position + n).map(String::as_str)
+    }
+
+    fn remaining(&self) -> usize {
+        self.args.len() - self.position
+    }
+
+    fn or(&mut self) -> Result<bool, ConditionError> {
+        let mut result = self.and()?;
+
+        while self.peek(0) == Some("-o") && self.remaining() > 1 {
+            self.position += 1;
+            result |= self.and()?;
+        }
+
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, ConditionError> {
+        let mut result = self.term()?;
+
+        while self.peek(0) == Some("-a") && self.remaining() > 1 {
+            self.position += 1;
+            result &= self.term()?;
+        }
+
+        Ok(result)
+    }
+
+    fn term(&mut self) -> Result<bool, ConditionError> {
+        let Some(first) = self.peek(0) else {
+            return Err(ConditionError::UnaryOperator(
+                self.args.last().cloned().unwrap_or_default(),
+            ));
+        };
+
+        // a binary operator in second place wins, so `[ ! = x ]` compares
+        if let (Some(operator), Some(right)) = (self.peek(1), self.peek(2)) {
+            if is_binary_operator(operator) && operator != "=~" {
+                let result = binary(operator, first, right, parse_integer)?;
+                self.position += 3;
+                return Ok(result);
+            }
+        }
+
+        if first == "!" && self.remaining() > 1 {
+            self.position += 1;
+            return Ok(!self.term()?);
+        }
+
+        if first == "(" && self.remaining() > 1 {
+            self.position += 1;
+            let result = self.or()?;
+
+            if self.peek(0) != Some(")") {
+                return Err(ConditionError::ClosingParen);
+            }
+            self.position += 1;
+
+            return Ok(result);
+        }
+
+        if is_unary_operator(first) {
+            if let Some(operand) = self.peek(1) {
+                let result = unary(first, operand);
+                self.position += 2;
+                return Ok(result);
+            }
+        }
+
+        if self.remaining() > 1 && !matches!(self.peek(1), Some("-a" | "-o" | ")")) {
+            return Err(ConditionError::UnaryOperator(first.to_string()));
+        }
+
+        self.position += 1;
+        Ok(!first.is_empty())
+    }
+}
+
+/// Evaluates the expression of `[[ ... ]]`.
+pub fn evaluate(shell: &mut Shell, condition: &Condition) -> Result<bool, ConditionError> {
+    match condition {
+        Condition::Unary { operator, operand } => {
+            let operand = expand_to_string(shell, operand)?;
+            Ok(unary(operator, &operand))
+        }
+        Condition::Binary {
+            operator,
+            left,
+            right,
+        } => {
+            let left = expand_to_string(shell, left)?;
+
+            match operator.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern = expand_to_pattern(shell, right)?;
+                    let matches = Pattern::new(&pattern, true).matches(&left);
+                    Ok(matches == (operator != "!="))
+                }
+                "=~" => {
+                    let regex = expand_to_regex(shell, right)?;
+                    regex_match(shell, &left, &regex)
+                }
+                operator => {
+                    let right = expand_to_string(shell, right)?;
+                    binary(operator, &left, &right, |operand| {
+                        expand::arithmetic(shell, operand)
+                            .map_err(|_| ConditionError::Integer(operand.to_string()))
+                    })
+                }
+            }
+        }
+        Condition::Word(word) => Ok(!expand_to_string(shell, word)?.is_empty()),
+        Condition::Not(condition) => Ok(!evaluate(shell, condition)?),
+        Condition::And(left, right) => Ok(evaluate(shell, left)? && evaluate(shell, right)?),
+        Condition::Or(left, right) => Ok(evaluate(shell, left)? || evaluate(shell, right)?),
+    }
+}
+
+/// Matches `text` against `regex`, storing the match and the text of its
+/// groups in `BASH_REMATCH`.
+fn regex_match(shell: &mut Shell, text: &str, regex: &str) -> Result<bool, ConditionError> {
+    let compiled = Regex::new(regex).map_err(|_| ConditionError::Regex(regex.to_string()))?;
+
+    let Some(captures) = compiled.captures(text) else {
+        shell.variables_mut().unset("BASH_REMATCH");
+        return Ok(false);
+    };
+
+    let groups = captures
+        .iter()
+        .map(|group| group.map_or_else(String::new, |group| group.as_str().to_string()))
+        .collect();
+    shell.variables_mut().set_array("BASH_REMATCH", groups);
+
+    Ok(true)
+}
+
+fn unary(operator: &str, operand: &str) -> bool {
+    let path = Path::new(operand);
+
+    match operator {
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-s" => fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0),
+        "-L" | "-h" => path.is_symlink(),
+        "-r" => accessible(path, libc::R_OK),
+        "-w" => accessible(path, libc::W_OK),
+        "-x" => accessible(path, libc::X_OK),
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        operator => unreachable!("unknown unary operator {operator}"),
+    }
+}
+
+fn binary<F>(
+    operator: &str,
+    left: &str,
+    right: &str,
+    mut integer: F,
+) -> Result<bool, ConditionError>
+where
+    F: FnMut(&str) -> Result<i64, ConditionError>,
+{
+    Ok(match operator {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            let (left, right) = (integer(left)?, integer(right)?);
+
+            match operator {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            }
+        }
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        operator => unreachable!("unknown binary operator {operator}"),
+    })
+}
+
+fn parse_integer(operand: &str) -> Result<i64, ConditionError> {
+    operand
+        .trim()
+        .parse()
+        .map_err(|_| ConditionError::Integer(operand.to_string()))
+}
+
+fn modified(path: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Whether the current user may access `path` in the given `mode`.
+fn accessible(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    // SAFETY: `path` is a valid C string
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
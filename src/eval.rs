@@ -1,69 +1,601 @@
 use std::{
-    fs::OpenOptions,
-    io::{self},
-    process::Stdio,
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    process,
+    rc::Rc,
 };
 
 use crate::{
-    commands::{Command, Executable},
-    parser::{Expr, Stream},
-    shell::Shell,
+    commands::{Command, Executable, Process},
+    condition,
+    expand::{
+        self, ExpansionError, expand_assignment, expand_to_pattern, expand_to_string, expand_word,
+        expand_words,
+    },
+    glob::Pattern,
+    parser::{CaseItem, CaseTerminator, Expr, Parser, Stream},
+    prompt,
+    shell::{Flow, Shell},
+    stdio::{Input, Output},
+    tokenizer::Word,
 };
 
 impl Executable for Expr {
-    fn execute<I, O, E>(
+    fn execute(
         &self,
         shell: &mut Shell,
-        stdin: I,
-        stdout: O,
-        stderr: E,
-    ) -> Option<std::process::Child>
-    where
-        I: Into<Stdio>,
-        O: Into<Stdio> + io::Write,
-        E: Into<Stdio> + io::Write,
-    {
+        stdin: Input,
+        stdout: Output,
+        mut stderr: Output,
+    ) -> Process {
         match self {
-            Expr::Command { name, args } => {
-                let command = Command::new(name.clone(), args.clone());
-                command.execute(shell, stdin, stdout, stderr)
+            Expr::Command { assignments, words } => {
+                simple_command(shell, assignments, words, stdin, stdout, stderr)
             }
             Expr::Redirect { src, stream, dest } => {
-                let file = OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(dest)
-                    .unwrap();
-
-                match stream {
-                    Stream::Stdin => todo!(),
-                    Stream::Stdout => src.execute(shell, stdin, file, stderr),
-                    Stream::Stderr => src.execute(shell, stdin, stdout, file),
-                }
+                redirect(shell, src, stream, dest, false, stdin, stdout, stderr)
             }
             Expr::Append { src, stream, dest } => {
-                let file = OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(dest)
-                    .unwrap();
-
-                match stream {
-                    Stream::Stdin => todo!(),
-                    Stream::Stdout => src.execute(shell, stdin, file, stderr),
-                    Stream::Stderr => src.execute(shell, stdin, stdout, file),
+                redirect(shell, src, stream, dest, true, stdin, stdout, stderr)
+            }
+            Expr::Pipe { src, dest } => pipe(shell, src, dest, stdin, stdout, stderr),
+            Expr::Arithmetic(expression) => match expand::arithmetic(shell, expression) {
+                Ok(value) => Process::Exited(i32::from(value == 0)),
+                Err(e) => {
+                    writeln!(stderr, "((: {e}").unwrap();
+                    Process::Exited(1)
+                }
+            },
+            Expr::List(commands) => {
+                let (last, commands) = commands.split_last().unwrap();
+
+                for command in commands {
+                    let status = run(shell, command, &stdin, &stdout, &stderr);
+
+                    if shell.flow().is_some() {
+                        return Process::Exited(status);
+                    }
                 }
+
+                last.execute(shell, stdin, stdout, stderr)
+            }
+            Expr::And { left, right } => match run(shell, left, &stdin, &stdout, &stderr) {
+                0 if shell.flow().is_none() => right.execute(shell, stdin, stdout, stderr),
+                status => Process::Exited(status),
+            },
+            Expr::Or { left, right } => match run(shell, left, &stdin, &stdout, &stderr) {
+                status if status == 0 || shell.flow().is_some() => Process::Exited(status),
+                _ => right.execute(shell, stdin, stdout, stderr),
+            },
+            Expr::Not(pipeline) => {
+                let status = pipeline.execute(shell, stdin, stdout, stderr).wait();
+                Process::Exited(i32::from(status == 0))
             }
-            Expr::Pipe { src, dest } => {
-                let (pipe_reader, pipe_writer) = std::io::pipe().unwrap();
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let status = run(shell, condition, &stdin, &stdout, &stderr);
 
-                let child1 = src.execute(shell, stdin, pipe_writer, stderr);
-                let child2 = dest.execute(shell, pipe_reader, stdout, io::stderr());
-                child2.map(|mut child| child.wait());
+                if shell.flow().is_some() {
+                    Process::Exited(status)
+                } else if status == 0 {
+                    then.execute(shell, stdin, stdout, stderr)
+                } else if let Some(otherwise) = otherwise {
+                    otherwise.execute(shell, stdin, stdout, stderr)
+                } else {
+                    Process::Exited(0)
+                }
+            }
+            Expr::While { condition, body } => {
+                while_loop(shell, condition, body, true, &stdin, &stdout, &stderr)
+            }
+            Expr::Until { condition, body } => {
+                while_loop(shell, condition, body, false, &stdin, &stdout, &stderr)
+            }
+            Expr::For { name, words, body } => {
+                for_loop(shell, name, words, body, &stdin, &stdout, &mut stderr)
+            }
+            Expr::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                let expressions = [init, condition, update].map(String::as_str);
+                arithmetic_for_loop(shell, expressions, body, &stdin, &stdout, &mut stderr)
+            }
+            Expr::Case { word, items } => case(shell, word, items, &stdin, &stdout, &mut stderr),
+            Expr::Function { name, body } => {
+                shell.define_function(name, Rc::clone(body));
+                Process::Exited(0)
+            }
+            Expr::Group(body) => body.execute(shell, stdin, stdout, stderr),
+            Expr::Subshell(body) => subshell(shell, body, stdin, stdout, stderr),
+            Expr::Conditional(condition) => match condition::evaluate(shell, condition) {
+                Ok(result) => Process::Exited(i32::from(!result)),
+                Err(e) => {
+                    writeln!(stderr, "{e}").unwrap();
+                    Process::Exited(2)
+                }
+            },
+        }
+    }
+}
 
-                child1
+fn pipe(
+    shell: &mut Shell,
+    src: &Expr,
+    dest: &Expr,
+    stdin: Input,
+    stdout: Output,
+    stderr: Output,
+) -> Process {
+    let (pipe_reader, pipe_writer) = std::io::pipe().unwrap();
+
+    let src_stdout = Output::Pipe(pipe_writer);
+    let src_stderr = stderr.try_clone().unwrap();
+
+    // anything the shell runs itself has to be forked, or it would fill the
+    // pipe before the command reading from it starts
+    let src = if runs_binary(shell, src) {
+        src.execute(shell, stdin, src_stdout, src_stderr)
+    } else {
+        let reader = pipe_reader.as_raw_fd();
+
+        fork(shell, |shell| {
+            // the reading end has to be closed for writes to fail once the
+            // reader exits, and those should end the writer quietly
+            // SAFETY: the child exits without using or dropping the reader
+            unsafe {
+                libc::close(reader);
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
             }
+            src.execute(shell, stdin, src_stdout, src_stderr).wait()
+        })
+    };
+    let dest = dest.execute(shell, Input::Pipe(pipe_reader), stdout, stderr);
+
+    match src {
+        Process::Pipeline(mut processes) => {
+            processes.push(dest);
+            Process::Pipeline(processes)
+        }
+        src => Process::Pipeline(vec![src, dest]),
+    }
+}
+
+/// Whether `expr` is a simple command that runs an external program, which
+/// writes to a pipe concurrently without the shell forking.
+fn runs_binary(shell: &Shell, expr: &Expr) -> bool {
+    match expr {
+        Expr::Command { words, .. } => words
+            .first()
+            .and_then(Word::as_literal)
+            .is_some_and(|name| Command::is_binary(shell, name)),
+        Expr::Redirect { src, .. } | Expr::Append { src, .. } => runs_binary(shell, src),
+        _ => false,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn redirect(
+    shell: &mut Shell,
+    src: &Expr,
+    stream: &Stream,
+    dest: &Word,
+    append: bool,
+    stdin: Input,
+    stdout: Output,
+    mut stderr: Output,
+) -> Process {
+    let Some(dest) = redirect_target(shell, dest, &mut stderr) else {
+        return Process::Exited(1);
+    };
+
+    let file = match stream {
+        Stream::Stdin if !append => File::open(&dest),
+        _ => OpenOptions::new()
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .create(true)
+            .open(&dest),
+    };
+
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            writeln!(stderr, "{dest}: {}", error_message(&e)).unwrap();
+            return Process::Exited(1);
+        }
+    };
+
+    match stream {
+        Stream::Stdin => src.execute(shell, Input::File(file), stdout, stderr),
+        Stream::Stdout => src.execute(shell, stdin, Output::File(file), stderr),
+        Stream::Stderr => src.execute(shell, stdin, stdout, Output::File(file)),
+    }
+}
+
+/// Describes an I/O error the way the C library does, without the
+/// "(os error N)" suffix of its `Display` implementation.
+pub fn error_message(e: &io::Error) -> String {
+    match e.raw_os_error() {
+        // SAFETY: strerror returns a pointer to a valid C string
+        Some(code) => unsafe { CStr::from_ptr(libc::strerror(code)) }
+            .to_string_lossy()
+            .into_owned(),
+        None => e.to_string(),
+    }
+}
+
+/// Consumes a `break` or `continue` that reached the innermost loop, returning
+/// whether that loop goes on with its next iteration.
+fn next_iteration(shell: &mut Shell) -> bool {
+    match shell.take_flow() {
+        None | Some(Flow::Continue(1)) => true,
+        Some(Flow::Break(1)) => false,
+        Some(Flow::Return) => {
+            shell.set_flow(Flow::Return);
+            false
+        }
+        Some(Flow::Break(n)) => {
+            shell.set_flow(Flow::Break(n - 1));
+            false
+        }
+        Some(Flow::Continue(n)) => {
+            shell.set_flow(Flow::Continue(n - 1));
+            false
+        }
+    }
+}
+
+/// Runs the body of a loop for as long as `next` says so, keeping track of
+/// the loop nesting for `break` and `continue`.
+fn run_loop(shell: &mut Shell, mut next: impl FnMut(&mut Shell) -> Option<i32>) -> Process {
+    let loop_depth = shell.loop_depth();
+    shell.set_loop_depth(loop_depth + 1);
+
+    let mut status = 0;
+
+    while let Some(body_status) = next(shell) {
+        status = body_status;
+
+        if !next_iteration(shell) {
+            break;
         }
     }
+
+    shell.set_loop_depth(loop_depth);
+    Process::Exited(status)
+}
+
+/// Runs `while` loops, and `until` loops when `expected` is false.
+fn while_loop(
+    shell: &mut Shell,
+    condition: &Expr,
+    body: &Expr,
+    expected: bool,
+    stdin: &Input,
+    stdout: &Output,
+    stderr: &Output,
+) -> Process {
+    run_loop(shell, |shell| {
+        let status = run(shell, condition, stdin, stdout, stderr);
+
+        if shell.flow().is_some() {
+            return Some(status);
+        }
+
+        if (status == 0) != expected {
+            return None;
+        }
+
+        Some(run(shell, body, stdin, stdout, stderr))
+    })
+}
+
+fn for_loop(
+    shell: &mut Shell,
+    name: &str,
+    words: &[Word],
+    body: &Expr,
+    stdin: &Input,
+    stdout: &Output,
+    stderr: &mut Output,
+) -> Process {
+    let fields = match expand_words(shell, words) {
+        Ok(fields) => fields,
+        Err(e) => {
+            writeln!(stderr, "{e}").unwrap();
+            return Process::Exited(1);
+        }
+    };
+
+    let mut fields = fields.into_iter();
+
+    run_loop(shell, |shell| {
+        shell.variables_mut().set(name, fields.next()?);
+        Some(run(shell, body, stdin, stdout, stderr))
+    })
+}
+
+/// Runs `for ((init; condition; update))`, where an empty condition is true.
+fn arithmetic_for_loop(
+    shell: &mut Shell,
+    [init, condition, update]: [&str; 3],
+    body: &Expr,
+    stdin: &Input,
+    stdout: &Output,
+    stderr: &mut Output,
+) -> Process {
+    if loop_expression(shell, init, stderr).is_none() {
+        return Process::Exited(1);
+    }
+
+    let mut first = true;
+    let mut failed = false;
+
+    let process = run_loop(shell, |shell| {
+        if !first && loop_expression(shell, update, stderr).is_none() {
+            failed = true;
+            return None;
+        }
+        first = false;
+
+        match loop_expression(shell, condition, stderr) {
+            Some(0) => None,
+            Some(_) => Some(run(shell, body, stdin, stdout, stderr)),
+            None => {
+                failed = true;
+                None
+            }
+        }
+    });
+
+    if failed { Process::Exited(1) } else { process }
+}
+
+fn loop_expression(shell: &mut Shell, expression: &str, stderr: &mut Output) -> Option<i64> {
+    if expression.is_empty() {
+        return Some(1);
+    }
+
+    match expand::arithmetic(shell, expression) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            writeln!(stderr, "((: {e}").unwrap();
+            None
+        }
+    }
+}
+
+fn case(
+    shell: &mut Shell,
+    word: &Word,
+    items: &[CaseItem],
+    stdin: &Input,
+    stdout: &Output,
+    stderr: &mut Output,
+) -> Process {
+    let subject = match expand_to_string(shell, word) {
+        Ok(subject) => subject,
+        Err(e) => {
+            writeln!(stderr, "{e}").unwrap();
+            return Process::Exited(1);
+        }
+    };
+
+    let mut status = 0;
+    let mut fall_through = false;
+
+    for item in items {
+        if !fall_through {
+            match case_item_matches(shell, item, &subject) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    writeln!(stderr, "{e}").unwrap();
+                    return Process::Exited(1);
+                }
+            }
+        }
+
+        status = match &item.body {
+            Some(body) => run(shell, body, stdin, stdout, stderr),
+            None => 0,
+        };
+
+        if shell.flow().is_some() || item.terminator == CaseTerminator::Break {
+            break;
+        }
+
+        fall_through = item.terminator == CaseTerminator::FallThrough;
+    }
+
+    Process::Exited(status)
+}
+
+fn case_item_matches(
+    shell: &mut Shell,
+    item: &CaseItem,
+    subject: &str,
+) -> Result<bool, ExpansionError> {
+    for pattern in &item.patterns {
+        let pattern = expand_to_pattern(shell, pattern)?;
+
+        if Pattern::new(&pattern, shell.options().extglob).matches(subject) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn simple_command(
+    shell: &mut Shell,
+    assignments: &[Word],
+    words: &[Word],
+    stdin: Input,
+    stdout: Output,
+    mut stderr: Output,
+) -> Process {
+    let expanded = expand_words(shell, words).and_then(|fields| {
+        let values = assignments
+            .iter()
+            .map(|assignment| expand_assignment(shell, assignment))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((fields, values))
+    });
+
+    let (fields, values) = match expanded {
+        Ok(expanded) => expanded,
+        Err(e) => {
+            writeln!(stderr, "{e}").unwrap();
+            return Process::Exited(1);
+        }
+    };
+
+    if shell.options().xtrace {
+        trace(shell, &values, &fields, &mut stderr);
+    }
+
+    let mut fields = fields.into_iter();
+
+    let Some(name) = fields.next() else {
+        for (name, value) in values {
+            shell.variables_mut().set(&name, value);
+        }
+        return Process::Exited(0);
+    };
+
+    // assignments before a command only apply to that command
+    let saved: Vec<_> = values
+        .iter()
+        .map(|(name, _)| (name.clone(), shell.variables().snapshot(name)))
+        .collect();
+
+    for (name, value) in values {
+        shell.variables_mut().set(&name, value);
+        shell.variables_mut().export(&name);
+    }
+
+    let command = Command::new(shell, name, fields.collect());
+    let process = command.execute(shell, stdin, stdout, stderr);
+
+    for (name, variable) in saved {
+        shell.variables_mut().restore(&name, variable);
+    }
+
+    process
+}
+
+/// Writes a command about to run to stderr after the expansion of `$PS4`.
+fn trace(shell: &mut Shell, values: &[(String, String)], fields: &[String], stderr: &mut Output) {
+    let ps4 = shell.variables().get("PS4").unwrap_or("+ ").to_string();
+    let prompt = prompt::expand(shell, &ps4);
+
+    let words: Vec<String> = values
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .chain(fields.iter().cloned())
+        .collect();
+
+    writeln!(stderr, "{}{}", prompt.text, words.join(" ")).unwrap();
+}
+
+/// Runs one part of a compound command to completion on copies of its
+/// streams, recording its exit status for the next part.
+fn run(shell: &mut Shell, expr: &Expr, stdin: &Input, stdout: &Output, stderr: &Output) -> i32 {
+    let status = expr
+        .execute(
+            shell,
+            stdin.try_clone().unwrap(),
+            stdout.try_clone().unwrap(),
+            stderr.try_clone().unwrap(),
+        )
+        .wait();
+
+    shell.set_status(status);
+    status
+}
+
+fn redirect_target(shell: &mut Shell, dest: &Word, stderr: &mut Output) -> Option<String> {
+    match expand_word(shell, dest) {
+        Ok(fields) if fields.len() == 1 => fields.into_iter().next(),
+        Ok(_) => {
+            writeln!(stderr, "ambiguous redirect").unwrap();
+            None
+        }
+        Err(e) => {
+            writeln!(stderr, "{e}").unwrap();
+            None
+        }
+    }
+}
+
+/// Runs `expr` in a forked copy of the shell, so that nothing it changes
+/// leaks back into this one.
+pub fn subshell(
+    shell: &mut Shell,
+    expr: &Expr,
+    stdin: Input,
+    stdout: Output,
+    stderr: Output,
+) -> Process {
+    fork(shell, |shell| {
+        expr.execute(shell, stdin, stdout, stderr).wait()
+    })
+}
+
+/// Calls `run` in a forked copy of the shell, which exits with the status it
+/// returns.
+fn fork(shell: &mut Shell, run: impl FnOnce(&mut Shell) -> i32) -> Process {
+    io::stdout().flush().unwrap();
+
+    // SAFETY: the shell is single-threaded, so the child can keep using it
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "failed to fork: {}", io::Error::last_os_error());
+
+    if pid == 0 {
+        let status = run(shell);
+        let _ = io::stdout().flush();
+        process::exit(status);
+    }
+
+    Process::Forked(pid)
+}
+
+/// Runs `source` in a forked copy of the shell and returns what it wrote to
+/// stdout, without trailing newlines.
+pub fn command_substitution(shell: &mut Shell, source: &str) -> String {
+    let ast = match Parser::parse_source(source, shell.aliases()) {
+        Ok(Some(ast)) => ast,
+        Ok(None) => return String::new(),
+        Err(e) => {
+            eprintln!("{e}");
+            return String::new();
+        }
+    };
+
+    let (mut reader, writer) = io::pipe().unwrap();
+    let child = subshell(
+        shell,
+        &ast,
+        Input::Inherit,
+        Output::Pipe(writer),
+        Output::Stderr,
+    );
+
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).unwrap();
+    shell.set_status(child.wait());
+
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    output.truncate(output.trim_end_matches('\n').len());
+    output
 }
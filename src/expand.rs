@@ -0,0 +1,407 @@
+use thiserror::Error;
+
+use crate::{
+    arith::{self, ArithmeticError},
+    brace, eval,
+    glob::{self, Pattern},
+    param::{self, Expanded},
+    shell::Shell,
+    tokenizer::{Segment, Tokenizer, Word},
+    users,
+};
+
+#[derive(Debug, Error)]
+pub enum ExpansionError {
+    #[error("no match: {0}")]
+    NoMatch(String),
+    #[error(transparent)]
+    Arithmetic(#[from] ArithmeticError),
+    #[error("${{{0}}}: bad substitution")]
+    BadSubstitution(String),
+    #[error("{0}: {1}")]
+    Parameter(String, String),
+}
+
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Expands every word into the fields that are handed to a command.
+pub fn expand_words<'a>(
+    shell: &mut Shell,
+    words: impl IntoIterator<Item = &'a Word>,
+) -> Result<Vec<String>, ExpansionError> {
+    let mut fields = Vec::new();
+
+    for word in words {
+        fields.extend(expand_word(shell, word)?);
+    }
+
+    Ok(fields)
+}
+
+/// Expands a single word in the order POSIX prescribes, after brace
+/// expansion: tilde expansion, then parameter expansion, command substitution
+/// and arithmetic expansion, then field splitting, pathname expansion and
+/// finally quote removal.
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<Vec<String>, ExpansionError> {
+    let mut expanded = Vec::new();
+
+    for word in brace::expand(word) {
+        let word = tilde_expansion(shell, &word);
+
+        let mut substituted = Substituted::default();
+        substitute(shell, word.segments(), Context::Word, &mut substituted)?;
+
+        for field in field_splitting(shell, substituted) {
+            expanded.extend(pathname_expansion(shell, &field)?);
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Replaces unquoted tilde prefixes at the start of the word, and in words
+/// that look like assignments also after the `=` and every unquoted `:`.
+fn tilde_expansion(shell: &Shell, word: &Word) -> Word {
+    let assignment = word.assignment_name().is_some();
+    let segments = word.segments();
+    let mut expanded = Word::default();
+    let mut seen_equals = false;
+
+    for (i, segment) in segments.iter().enumerate() {
+        let Segment::Literal(literal) = segment else {
+            expanded.push_segment(segment.clone());
+            continue;
+        };
+
+        let is_last_segment = i == segments.len() - 1;
+        let mut can_expand = i == 0;
+        let mut rest = literal.as_str();
+
+        while let Some(c) = rest.chars().next() {
+            if c == '~' && can_expand {
+                let end = rest.find(|c| c == '/' || (assignment && c == ':'));
+
+                // a tilde prefix must not run into quoted characters
+                if end.is_some() || is_last_segment {
+                    let end = end.unwrap_or(rest.len());
+
+                    if let Some(home) = tilde_replacement(shell, &rest[1..end]) {
+                        expanded.push_quoted(&home);
+                        rest = &rest[end..];
+                        can_expand = false;
+                        continue;
+                    }
+                }
+            }
+
+            can_expand = assignment && (c == ':' || (c == '=' && !seen_equals));
+            seen_equals |= c == '=';
+
+            expanded.push_literal(&rest[..c.len_utf8()]);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    expanded
+}
+
+fn tilde_replacement(shell: &Shell, prefix: &str) -> Option<String> {
+    let variables = shell.variables();
+
+    match prefix {
+        "" => variables
+            .get("HOME")
+            .map(String::from)
+            .or_else(users::current_home_dir),
+        "+" => variables.get("PWD").map(String::from),
+        "-" => variables.get("OLDPWD").map(String::from),
+        user => users::home_dir(user),
+    }
+}
+
+/// Where a character of a word came from, which decides whether it takes part
+/// in field splitting and pathname expansion.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Unquoted text written in the word itself.
+    Literal,
+    /// Quoted text, or the result of a quoted expansion.
+    Quoted,
+    /// The unquoted result of an expansion, subject to field splitting.
+    Expanded,
+}
+
+#[derive(Clone, Copy)]
+enum Piece {
+    Char(char, Origin),
+    /// An empty pair of quotes, which still makes the word produce a field.
+    QuotedNull,
+    /// The boundary between two positional parameters in `$@`.
+    FieldBreak,
+}
+
+/// A word after substitution, before it is split into fields.
+#[derive(Default)]
+struct Substituted {
+    pieces: Vec<Piece>,
+}
+
+impl Substituted {
+    fn push_str(&mut self, s: &str, origin: Origin) {
+        if s.is_empty() && origin == Origin::Quoted {
+            self.pieces.push(Piece::QuotedNull);
+        }
+        self.pieces
+            .extend(s.chars().map(|c| Piece::Char(c, origin)));
+    }
+
+    fn push_expansion(&mut self, s: &str, context: Context) {
+        let origin = match context {
+            Context::Word | Context::Operand => Origin::Expanded,
+            Context::Quoted => Origin::Quoted,
+            Context::Pattern => Origin::Literal,
+        };
+        self.push_str(s, origin);
+    }
+}
+
+/// A field after splitting, remembering which characters were quoted.
+#[derive(Debug, Default)]
+struct Field {
+    chars: Vec<(char, bool)>,
+}
+
+/// How the text of a segment is treated while substituting.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// Part of a command word: expansion results are split into fields.
+    Word,
+    /// Inside double quotes: nothing is split or matched as a pattern.
+    Quoted,
+    /// The operand of an unquoted parameter expansion, where even the
+    /// literal text is the result of an expansion.
+    Operand,
+    /// A pattern operand: nothing is split, but unquoted text stays active.
+    Pattern,
+}
+
+/// Performs parameter expansion, command substitution and arithmetic
+/// expansion from left to right.
+fn substitute(
+    shell: &mut Shell,
+    segments: &[Segment],
+    context: Context,
+    substituted: &mut Substituted,
+) -> Result<(), ExpansionError> {
+    for segment in segments {
+        match segment {
+            Segment::Literal(literal) => {
+                let origin = match context {
+                    Context::Word | Context::Pattern => Origin::Literal,
+                    Context::Quoted => Origin::Quoted,
+                    Context::Operand => Origin::Expanded,
+                };
+                substituted.push_str(literal, origin);
+            }
+            Segment::Quoted(text) => substituted.push_str(text, Origin::Quoted),
+            Segment::DoubleQuoted(word) => {
+                // `"$@"` without positional parameters expands to no field at all
+                let all_positional = matches!(word.segments(), [Segment::Parameter(p)] if p == "@");
+
+                if !all_positional || !shell.positional().is_empty() {
+                    substituted.push_str("", Origin::Quoted);
+                }
+                substitute(shell, word.segments(), Context::Quoted, substituted)?;
+            }
+            Segment::Parameter(text)
+                if text == "@" || (text == "*" && context != Context::Quoted) =>
+            {
+                for (i, parameter) in shell.positional().iter().enumerate() {
+                    if i > 0 {
+                        substituted.pieces.push(Piece::FieldBreak);
+                    }
+                    substituted.push_expansion(parameter, context);
+                }
+            }
+            Segment::Arithmetic(expression) => {
+                let value = arithmetic(shell, expression)?;
+                substituted.push_expansion(&value.to_string(), context);
+            }
+            Segment::CommandSubstitution(source) => {
+                let output = eval::command_substitution(shell, source);
+                substituted.push_expansion(&output, context);
+            }
+            Segment::Parameter(text) => match param::expand(shell, text)? {
+                Expanded::Text(value) => substituted.push_expansion(&value, context),
+                Expanded::Operand(word) => {
+                    let context = match context {
+                        Context::Word => Context::Operand,
+                        context => context,
+                    };
+                    substitute(shell, word.segments(), context, substituted)?;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the unquoted results of expansions on the characters of `$IFS`.
+///
+/// Runs of IFS whitespace delimit fields and are dropped at the start and the
+/// end, while every other IFS character delimits a field on its own, so two of
+/// them in a row produce an empty field.
+fn field_splitting(shell: &Shell, substituted: Substituted) -> Vec<Field> {
+    let ifs = shell.variables().get("IFS").unwrap_or(DEFAULT_IFS);
+
+    let mut fields = Vec::new();
+    let mut current = Field::default();
+    let mut started = false;
+    let mut after_whitespace = false;
+
+    for piece in substituted.pieces {
+        match piece {
+            Piece::Char(c, Origin::Expanded) if ifs.contains(c) => {
+                let whitespace = DEFAULT_IFS.contains(c);
+
+                if started {
+                    fields.push(std::mem::take(&mut current));
+                    started = false;
+                    after_whitespace = whitespace;
+                } else if !whitespace {
+                    if !after_whitespace {
+                        fields.push(Field::default());
+                    }
+                    after_whitespace = false;
+                }
+            }
+            Piece::Char(c, origin) => {
+                current.chars.push((c, origin == Origin::Quoted));
+                started = true;
+                after_whitespace = false;
+            }
+            Piece::QuotedNull => started = true,
+            Piece::FieldBreak => {
+                if started {
+                    fields.push(std::mem::take(&mut current));
+                    started = false;
+                }
+                after_whitespace = true;
+            }
+        }
+    }
+
+    if started {
+        fields.push(current);
+    }
+
+    fields
+}
+
+/// Evaluates the expression of an arithmetic expansion after expanding the
+/// parameters and command substitutions in it.
+pub fn arithmetic(shell: &mut Shell, expression: &str) -> Result<i64, ExpansionError> {
+    let expression = expand_to_string(shell, &Tokenizer::operand(expression))?;
+    Ok(arith::evaluate(&expression, shell.variables_mut())?)
+}
+
+/// Expands a word without field splitting or pathname expansion, as done for
+/// assignment values.
+pub fn expand_to_string(shell: &mut Shell, word: &Word) -> Result<String, ExpansionError> {
+    let word = tilde_expansion(shell, word);
+
+    let mut substituted = Substituted::default();
+    substitute(shell, word.segments(), Context::Quoted, &mut substituted)?;
+
+    Ok(field_splitting(shell, substituted)
+        .iter()
+        .map(quote_removal)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Expands a word into a pattern for the glob engine.
+pub fn expand_to_pattern(shell: &mut Shell, word: &Word) -> Result<String, ExpansionError> {
+    let mut substituted = Substituted::default();
+    substitute(shell, word.segments(), Context::Pattern, &mut substituted)?;
+
+    Ok(field_splitting(shell, substituted)
+        .iter()
+        .map(to_pattern)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Expands a word into a regular expression, in which quoted characters
+/// match themselves.
+pub fn expand_to_regex(shell: &mut Shell, word: &Word) -> Result<String, ExpansionError> {
+    let mut substituted = Substituted::default();
+    substitute(shell, word.segments(), Context::Pattern, &mut substituted)?;
+
+    Ok(field_splitting(shell, substituted)
+        .iter()
+        .map(|field| escape_quoted(field, "\\.+*?()|[]{}^$"))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Expands an `name=value` word into the name and the expanded value.
+pub fn expand_assignment(
+    shell: &mut Shell,
+    word: &Word,
+) -> Result<(String, String), ExpansionError> {
+    let assignment = expand_to_string(shell, word)?;
+    let (name, value) = assignment.split_once('=').unwrap();
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn pathname_expansion(shell: &Shell, field: &Field) -> Result<Vec<String>, ExpansionError> {
+    let options = shell.options();
+    let pattern = to_pattern(field);
+
+    if !Pattern::new(&pattern, options.extglob).has_wildcards() {
+        return Ok(vec![quote_removal(field)]);
+    }
+
+    let matches = glob::expand(&pattern, options.glob());
+
+    if !matches.is_empty() {
+        return Ok(matches);
+    }
+
+    if options.failglob {
+        return Err(ExpansionError::NoMatch(quote_removal(field)));
+    }
+
+    if options.nullglob {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![quote_removal(field)])
+}
+
+/// Builds a glob pattern in which every quoted character is escaped.
+fn to_pattern(field: &Field) -> String {
+    escape_quoted(field, "\\*?[]()|!+@")
+}
+
+/// Joins the characters of a field, escaping the quoted ones among `special`
+/// with a backslash.
+fn escape_quoted(field: &Field, special: &str) -> String {
+    let mut escaped = String::new();
+
+    for &(c, quoted) in &field.chars {
+        if quoted && special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn quote_removal(field: &Field) -> String {
+    field.chars.iter().map(|&(c, _)| c).collect()
+}
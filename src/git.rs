@@ -0,0 +1,692 @@
+use std::{
+    cell::RefCell,
+    collections::{BinaryHeap, HashMap},
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use flate2::bufread::ZlibDecoder;
+use sha1_smol::Sha1;
+
+/// How many commits are read at most when counting how far a branch is ahead
+/// of and behind its upstream.
+const WALK_LIMIT: usize = 10_000;
+
+type ObjectId = [u8; 20];
+
+/// The type and the contents of an object.
+type Object = (u8, Rc<[u8]>);
+
+/// The state of the repository around the current directory, as shown in the
+/// prompt.
+pub struct Status {
+    pub branch: String,
+    /// Whether tracked files were changed since they were last staged.
+    /// Staged changes that are not committed yet don't count, like the `*`
+    /// of git's own prompt.
+    pub dirty: bool,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+impl Status {
+    /// The branch followed by `*` when dirty and the commits ahead of and
+    /// behind the upstream branch, like `main*↑1↓2`.
+    pub fn segment(&self) -> String {
+        let mut segment = self.branch.clone();
+
+        if self.dirty {
+            segment.push('*');
+        }
+        if self.ahead > 0 {
+            write!(segment, "↑{}", self.ahead).unwrap();
+        }
+        if self.behind > 0 {
+            write!(segment, "↓{}", self.behind).unwrap();
+        }
+
+        segment
+    }
+}
+
+/// What is remembered between prompts so that reading the repository stays
+/// cheap: the entries of the index until its modification time changes, the
+/// divergence of a branch until either of its commits changes, and whether a
+/// file whose modification time changed still has the staged contents.
+#[derive(Default)]
+pub struct Cache {
+    index: Option<(PathBuf, SystemTime, Vec<IndexEntry>)>,
+    divergence: HashMap<(ObjectId, ObjectId), Option<(usize, usize)>>,
+    unchanged: HashMap<(PathBuf, i64, ObjectId), bool>,
+}
+
+struct IndexEntry {
+    path: String,
+    mtime: u32,
+    size: u32,
+    mode: u32,
+    id: ObjectId,
+}
+
+/// The directories of a repository: `git_dir` holds `HEAD` and the index,
+/// `common_dir` the refs, objects and config, which differ in a worktree.
+struct Repository {
+    work_tree: PathBuf,
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+}
+
+impl Cache {
+    /// Reads the status of the repository containing `directory`, if any.
+    pub fn status(&mut self, directory: &Path) -> Option<Status> {
+        let repository = find_repository(directory)?;
+        let head = fs::read_to_string(repository.git_dir.join("HEAD")).ok()?;
+        let head = head.trim();
+
+        let (branch, head_id) = match head.strip_prefix("ref: ") {
+            Some(reference) => (
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+                repository.resolve(reference),
+            ),
+            // a detached head shows the abbreviated commit
+            None => (head.chars().take(7).collect(), parse_id(head)),
+        };
+
+        let dirty = self.dirty(&repository);
+
+        let upstream = repository
+            .upstream(&branch)
+            .and_then(|upstream| repository.resolve(&upstream));
+
+        let (ahead, behind) = match (head_id, upstream) {
+            (Some(head), Some(upstream)) => self
+                .divergence
+                .entry((head, upstream))
+                .or_insert_with(|| divergence(&repository, head, upstream))
+                // too long a walk shows no counts rather than wrong ones
+                .unwrap_or((0, 0)),
+            _ => (0, 0),
+        };
+
+        Some(Status {
+            branch,
+            dirty,
+            ahead,
+            behind,
+        })
+    }
+
+    /// Whether a tracked file is missing or differs in size from what the
+    /// index recorded, or differs in modification time and no longer hashes
+    /// to the staged object.
+    fn dirty(&mut self, repository: &Repository) -> bool {
+        let path = repository.git_dir.join("index");
+        let Ok(mtime) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+            return false;
+        };
+
+        let cached =
+            matches!(&self.index, Some((cached, time, _)) if *cached == path && *time == mtime);
+        if !cached {
+            let entries = read_index(&path).unwrap_or_default();
+            self.index = Some((path, mtime, entries));
+            self.unchanged.clear();
+        }
+
+        let Some((_, _, entries)) = &self.index else {
+            return false;
+        };
+        let unchanged = &mut self.unchanged;
+
+        entries
+            .iter()
+            // submodules are repositories of their own
+            .filter(|entry| entry.mode & 0o170_000 != 0o160_000)
+            .any(|entry| {
+                let path = repository.work_tree.join(&entry.path);
+                let Ok(metadata) = fs::symlink_metadata(&path) else {
+                    return true;
+                };
+
+                // the index keeps the low 32 bits of both
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let (size, mtime) = (metadata.size() as u32, metadata.mtime() as u32);
+
+                if size != entry.size {
+                    return true;
+                }
+                if mtime == entry.mtime {
+                    return false;
+                }
+
+                // a file that was only touched still has the staged contents
+                !*unchanged
+                    .entry((path, metadata.mtime(), entry.id))
+                    .or_insert_with_key(|(path, _, id)| {
+                        blob_id(path, &metadata).is_some_and(|blob| blob == *id)
+                    })
+            })
+    }
+}
+
+/// The id git gives the contents of a file or the target of a symbolic link.
+fn blob_id(path: &Path, metadata: &fs::Metadata) -> Option<ObjectId> {
+    let contents = if metadata.is_symlink() {
+        fs::read_link(path)
+            .ok()?
+            .into_os_string()
+            .into_encoded_bytes()
+    } else {
+        fs::read(path).ok()?
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()).as_bytes());
+    hasher.update(&contents);
+    Some(hasher.digest().bytes())
+}
+
+/// Walks up from `directory` to the first one holding a `.git` directory, or
+/// a `.git` file pointing to one as in a worktree.
+fn find_repository(directory: &Path) -> Option<Repository> {
+    for work_dir in directory.ancestors() {
+        let dot_git = work_dir.join(".git");
+
+        let git_dir = if dot_git.is_dir() {
+            dot_git
+        } else if let Ok(contents) = fs::read_to_string(&dot_git) {
+            let target = contents.strip_prefix("gitdir:")?.trim();
+            work_dir.join(target)
+        } else {
+            continue;
+        };
+
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim()),
+            Err(_) => git_dir.clone(),
+        };
+
+        return Some(Repository {
+            work_tree: work_dir.to_path_buf(),
+            git_dir,
+            common_dir,
+        });
+    }
+
+    None
+}
+
+impl Repository {
+    /// The commit a reference points to, following symbolic references.
+    fn resolve(&self, reference: &str) -> Option<ObjectId> {
+        let loose = fs::read_to_string(self.git_dir.join(reference))
+            .or_else(|_| fs::read_to_string(self.common_dir.join(reference)));
+
+        let Ok(contents) = loose else {
+            return self.packed_ref(reference);
+        };
+
+        match contents.trim().strip_prefix("ref: ") {
+            Some(target) => self.resolve(target),
+            None => parse_id(contents.trim()),
+        }
+    }
+
+    fn packed_ref(&self, reference: &str) -> Option<ObjectId> {
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+
+        packed.lines().find_map(|line| {
+            let (id, name) = line.split_once(' ')?;
+            (name == reference).then(|| parse_id(id)).flatten()
+        })
+    }
+
+    /// The remote-tracking reference that `branch` is configured to follow.
+    fn upstream(&self, branch: &str) -> Option<String> {
+        let config = fs::read_to_string(self.common_dir.join("config")).ok()?;
+        let section = format!("[branch \"{branch}\"]");
+
+        let mut in_section = false;
+        let mut remote = None;
+        let mut merge = None;
+
+        for line in config.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_section = line == section;
+                continue;
+            }
+
+            if !in_section {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                match key.trim() {
+                    "remote" => remote = Some(value.trim().to_string()),
+                    "merge" => merge = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        let merge = merge?;
+        let merged_branch = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+
+        match remote?.as_str() {
+            // a branch following a local branch
+            "." => Some(merge.clone()),
+            remote => Some(format!("refs/remotes/{remote}/{merged_branch}")),
+        }
+    }
+}
+
+fn parse_id(hex: &str) -> Option<ObjectId> {
+    if hex.len() != 40 {
+        return None;
+    }
+
+    let mut id = [0; 20];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(id)
+}
+
+fn to_hex(id: &ObjectId) -> String {
+    id.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").unwrap();
+        hex
+    })
+}
+
+/// Reads the path, size, modification time and object id of the entries of an index
+/// file in version 2, 3 or 4.
+fn read_index(path: &Path) -> Option<Vec<IndexEntry>> {
+    let data = fs::read(path).ok()?;
+
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+
+    let version = read_u32(&data, 4)?;
+    let count = read_u32(&data, 8)?;
+    let mut entries = Vec::new();
+    let mut position = 12;
+    let mut previous = Vec::new();
+
+    for _ in 0..count {
+        let mtime = read_u32(&data, position + 8)?;
+        let mode = read_u32(&data, position + 24)?;
+        let size = read_u32(&data, position + 36)?;
+        let id = data.get(position + 40..position + 60)?.try_into().ok()?;
+        let flags = u16::from_be_bytes(data.get(position + 60..position + 62)?.try_into().ok()?);
+
+        let mut name_start = position + 62;
+        if version >= 3 && flags & 0x4000 != 0 {
+            name_start += 2;
+        }
+
+        let name = if version >= 4 {
+            // names are stored as the length to drop from the previous name
+            // and the suffix to append
+            let (strip, length) = read_offset(&data, name_start)?;
+            let suffix_start = name_start + length;
+            let suffix_end =
+                suffix_start + data.get(suffix_start..)?.iter().position(|&b| b == 0)?;
+
+            previous.truncate(previous.len().checked_sub(strip)?);
+            previous.extend_from_slice(&data[suffix_start..suffix_end]);
+            position = suffix_end + 1;
+            previous.clone()
+        } else {
+            let name_end = name_start + data.get(name_start..)?.iter().position(|&b| b == 0)?;
+            let name = data[name_start..name_end].to_vec();
+
+            // entries are padded with NULs to a multiple of 8 bytes
+            position += (name_end - position + 8) & !7;
+            name
+        };
+
+        entries.push(IndexEntry {
+            path: String::from_utf8_lossy(&name).into_owned(),
+            mtime,
+            size,
+            mode,
+            id,
+        });
+    }
+
+    Some(entries)
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Reads the variable-length offset encoding of packs and index names,
+/// returning the value and the number of bytes it took.
+fn read_offset(data: &[u8], position: usize) -> Option<(usize, usize)> {
+    let mut byte = *data.get(position)?;
+    let mut value = usize::from(byte & 0x7f);
+    let mut length = 1;
+
+    while byte & 0x80 != 0 {
+        byte = *data.get(position + length)?;
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+        length += 1;
+    }
+
+    Some((value, length))
+}
+
+/// Counts the commits reachable from `head` but not from `upstream`, and the
+/// other way around, or returns `None` if that takes reading too many.
+fn divergence(
+    repository: &Repository,
+    head: ObjectId,
+    upstream: ObjectId,
+) -> Option<(usize, usize)> {
+    const HEAD: u8 = 1;
+    const UPSTREAM: u8 = 2;
+    const BOTH: u8 = HEAD | UPSTREAM;
+
+    let mut objects = Objects::new(&repository.common_dir.join("objects"));
+    let mut sides = HashMap::from([(head, HEAD)]);
+    *sides.entry(upstream).or_default() |= UPSTREAM;
+
+    let mut queue = BinaryHeap::new();
+    for id in [head, upstream] {
+        queue.push((objects.commit(&id).0, id));
+    }
+
+    // newest first, like a merge base search: once every commit left is
+    // reachable from both sides, so are all of their ancestors
+    let mut read = 0;
+    while queue.iter().any(|(_, id)| sides[id] != BOTH) {
+        let Some((_, id)) = queue.pop() else {
+            break;
+        };
+
+        read += 1;
+        if read > WALK_LIMIT {
+            return None;
+        }
+
+        let side = sides[&id];
+        for parent in objects.commit(&id).1 {
+            let parent_side = sides.entry(parent).or_default();
+
+            if *parent_side | side != *parent_side {
+                *parent_side |= side;
+                queue.push((objects.commit(&parent).0, parent));
+            }
+        }
+    }
+
+    let count = |side| sides.values().filter(|&&s| s == side).count();
+    Some((count(HEAD), count(UPSTREAM)))
+}
+
+/// The object database, with loose objects and packs.
+struct Objects {
+    directory: PathBuf,
+    packs: Vec<Pack>,
+    /// The commit time and parents of the commits read so far.
+    commits: HashMap<ObjectId, (i64, Vec<ObjectId>)>,
+}
+
+struct Pack {
+    path: PathBuf,
+    ids: Vec<ObjectId>,
+    offsets: Vec<u64>,
+    /// The objects decoded so far by offset, as deltas often share bases.
+    decoded: RefCell<HashMap<u64, Object>>,
+}
+
+impl Objects {
+    fn new(directory: &Path) -> Self {
+        let packs = fs::read_dir(directory.join("pack"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+            .filter_map(|path| Pack::open(&path))
+            .collect();
+
+        Self {
+            directory: directory.to_path_buf(),
+            packs,
+            commits: HashMap::new(),
+        }
+    }
+
+    /// The committer time and the parents of a commit, with none of either
+    /// for a commit that is missing, as in a shallow clone.
+    fn commit(&mut self, id: &ObjectId) -> (i64, Vec<ObjectId>) {
+        if let Some(commit) = self.commits.get(id) {
+            return commit.clone();
+        }
+
+        let mut time = 0;
+        let mut parents = Vec::new();
+
+        if let Some(commit) = self.read(id) {
+            for line in String::from_utf8_lossy(&commit)
+                .lines()
+                .take_while(|line| !line.is_empty())
+            {
+                if let Some(parent) = line.strip_prefix("parent ") {
+                    parents.extend(parse_id(parent));
+                } else if let Some(committer) = line.strip_prefix("committer ") {
+                    // the time comes after the email, before the time zone
+                    time = committer
+                        .rsplit(' ')
+                        .nth(1)
+                        .and_then(|time| time.parse().ok())
+                        .unwrap_or(0);
+                }
+            }
+        }
+
+        self.commits.insert(*id, (time, parents.clone()));
+        (time, parents)
+    }
+
+    /// The contents of an object, without its header.
+    fn read(&self, id: &ObjectId) -> Option<Rc<[u8]>> {
+        let hex = to_hex(id);
+        let loose = self.directory.join(&hex[..2]).join(&hex[2..]);
+
+        if let Ok(file) = File::open(loose) {
+            let mut data = Vec::new();
+            ZlibDecoder::new(BufReader::new(file))
+                .read_to_end(&mut data)
+                .ok()?;
+
+            let header_end = data.iter().position(|&b| b == 0)?;
+            return Some(data.split_off(header_end + 1).into());
+        }
+
+        self.packs
+            .iter()
+            .find_map(|pack| Some(pack.read(pack.offset(id)?, self)?.1))
+    }
+}
+
+impl Pack {
+    /// Reads the object names and offsets of a version 2 pack index.
+    fn open(index: &Path) -> Option<Self> {
+        let data = fs::read(index).ok()?;
+
+        if data.get(..8)? != b"\xfftOc\x00\x00\x00\x02" {
+            return None;
+        }
+
+        let count = usize::try_from(read_u32(&data, 8 + 255 * 4)?).ok()?;
+        let ids_start = 8 + 256 * 4;
+        let offsets_start = ids_start + count * 24;
+        let large_offsets_start = offsets_start + count * 4;
+
+        let ids = (0..count)
+            .map(|i| {
+                data.get(ids_start + i * 20..ids_start + i * 20 + 20)?
+                    .try_into()
+                    .ok()
+            })
+            .collect::<Option<Vec<ObjectId>>>()?;
+
+        let offsets = (0..count)
+            .map(|i| {
+                let offset = read_u32(&data, offsets_start + i * 4)?;
+
+                if offset & 0x8000_0000 == 0 {
+                    return Some(u64::from(offset));
+                }
+
+                // the offset is an index into the table of 64-bit offsets
+                let position = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+                Some(u64::from_be_bytes(
+                    data.get(position..position + 8)?.try_into().ok()?,
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            path: index.with_extension("pack"),
+            ids,
+            offsets,
+            decoded: RefCell::default(),
+        })
+    }
+
+    fn offset(&self, id: &ObjectId) -> Option<u64> {
+        let i = self.ids.binary_search(id).ok()?;
+        Some(self.offsets[i])
+    }
+
+    /// The type and the contents of the object at `offset`, applying deltas.
+    fn read(&self, offset: u64, objects: &Objects) -> Option<Object> {
+        if let Some((kind, data)) = self.decoded.borrow().get(&offset) {
+            return Some((*kind, Rc::clone(data)));
+        }
+
+        let mut reader = BufReader::new(File::open(&self.path).ok()?);
+        reader.seek(SeekFrom::Start(offset)).ok()?;
+
+        let mut byte = read_byte(&mut reader)?;
+        let kind = (byte >> 4) & 0x7;
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?;
+        }
+
+        let base = match kind {
+            // OFS_DELTA: the base is earlier in this pack
+            6 => {
+                let mut byte = read_byte(&mut reader)?;
+                let mut distance = u64::from(byte & 0x7f);
+                while byte & 0x80 != 0 {
+                    byte = read_byte(&mut reader)?;
+                    distance = ((distance + 1) << 7) | u64::from(byte & 0x7f);
+                }
+                Some(self.read(offset.checked_sub(distance)?, objects)?)
+            }
+            // REF_DELTA: the base is named
+            7 => {
+                let mut id = [0; 20];
+                reader.read_exact(&mut id).ok()?;
+                match self.offset(&id) {
+                    Some(offset) => Some(self.read(offset, objects)?),
+                    None => Some((1, objects.read(&id)?)),
+                }
+            }
+            _ => None,
+        };
+
+        let mut data = Vec::new();
+        ZlibDecoder::new(reader).read_to_end(&mut data).ok()?;
+
+        let object: Object = match base {
+            Some((kind, base)) => (kind, apply_delta(&base, &data)?.into()),
+            None => (kind, data.into()),
+        };
+
+        self.decoded
+            .borrow_mut()
+            .insert(offset, (object.0, Rc::clone(&object.1)));
+        Some(object)
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> Option<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte).ok()?;
+    Some(byte[0])
+}
+
+/// Rebuilds an object from its base and a delta of copy and insert
+/// instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    let mut read_size = || {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(position)?;
+            position += 1;
+            size |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(size);
+            }
+        }
+    };
+
+    let _base_size = read_size()?;
+    let result_size = read_size()?;
+    let mut result = Vec::with_capacity(result_size);
+
+    while let Some(&op) = delta.get(position) {
+        position += 1;
+
+        if op & 0x80 != 0 {
+            let mut offset = 0;
+            let mut size = 0;
+
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= usize::from(*delta.get(position)?) << (i * 8);
+                    position += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= usize::from(*delta.get(position)?) << (i * 8);
+                    position += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else {
+            let size = usize::from(op);
+            result.extend_from_slice(delta.get(position..position + size)?);
+            position += size;
+        }
+    }
+
+    Some(result)
+}
@@ -0,0 +1,493 @@
+use std::fs;
+
+/// A compiled shell pattern as used by pathname expansion.
+///
+/// A backslash makes the following character match literally, which is how
+/// quoted parts of a word are kept out of pattern matching.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<PatternToken>,
+}
+
+#[derive(Debug, Clone)]
+enum PatternToken {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+    Extended {
+        kind: ExtendedKind,
+        alternatives: Vec<Vec<PatternToken>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ExtendedKind {
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+    ExactlyOne,
+    Not,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, extglob: bool) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut position = 0;
+        let tokens = compile(&chars, &mut position, extglob, false);
+
+        Self { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        match_tokens(&self.tokens, &text)
+    }
+
+    /// Matches a single file name, where wildcards never match a leading dot
+    /// unless `dotglob` is set, and `.` and `..` only match explicitly.
+    pub fn matches_file_name(&self, name: &str, dotglob: bool) -> bool {
+        if name.starts_with('.') {
+            let explicit_dot = matches!(self.tokens.first(), Some(PatternToken::Char('.')));
+
+            if !explicit_dot && (!dotglob || name == "." || name == "..") {
+                return false;
+            }
+        }
+
+        self.matches(name)
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        self.tokens
+            .iter()
+            .any(|token| !matches!(token, PatternToken::Char(_)))
+    }
+
+    /// The text this pattern matches, if it contains no wildcards.
+    fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                PatternToken::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn compile(chars: &[char], position: &mut usize, extglob: bool, nested: bool) -> Vec<PatternToken> {
+    let mut tokens = Vec::new();
+
+    while let Some(&c) = chars.get(*position) {
+        if nested && (c == '|' || c == ')') {
+            break;
+        }
+
+        *position += 1;
+
+        let token = match c {
+            '\\' => match chars.get(*position) {
+                Some(&escaped) => {
+                    *position += 1;
+                    PatternToken::Char(escaped)
+                }
+                None => PatternToken::Char('\\'),
+            },
+            '?' | '*' | '+' | '@' | '!' if extglob && chars.get(*position) == Some(&'(') => {
+                let start = *position - 1;
+                *position += 1;
+
+                if let Some(token) = extended(chars, position, c) {
+                    token
+                } else {
+                    *position = start + 1;
+                    PatternToken::Char(c)
+                }
+            }
+            '?' => PatternToken::AnyChar,
+            '*' => PatternToken::AnyString,
+            '[' => match class(chars, position) {
+                Some(token) => token,
+                None => PatternToken::Char('['),
+            },
+            c => PatternToken::Char(c),
+        };
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn extended(chars: &[char], position: &mut usize, operator: char) -> Option<PatternToken> {
+    let kind = match operator {
+        '?' => ExtendedKind::ZeroOrOne,
+        '*' => ExtendedKind::ZeroOrMore,
+        '+' => ExtendedKind::OneOrMore,
+        '@' => ExtendedKind::ExactlyOne,
+        _ => ExtendedKind::Not,
+    };
+
+    let mut alternatives = Vec::new();
+
+    loop {
+        alternatives.push(compile(chars, position, true, true));
+
+        match chars.get(*position) {
+            Some('|') => *position += 1,
+            Some(')') => {
+                *position += 1;
+                return Some(PatternToken::Extended { kind, alternatives });
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn class(chars: &[char], position: &mut usize) -> Option<PatternToken> {
+    let mut i = *position;
+    let mut items = Vec::new();
+
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut first = true;
+
+    loop {
+        let c = *chars.get(i)?;
+
+        if c == ']' && !first {
+            i += 1;
+            break;
+        }
+        first = false;
+
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+
+            if let Some(end) = rest.find(":]") {
+                let name = rest[..end].to_string();
+                i += 2 + name.chars().count() + 2;
+                items.push(ClassItem::Named(name));
+                continue;
+            }
+        }
+
+        let start = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        } else {
+            c
+        };
+        i += 1;
+
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&c| c != ']') {
+            let mut end = chars[i + 1];
+            i += 2;
+
+            if end == '\\' {
+                end = *chars.get(i)?;
+                i += 1;
+            }
+
+            items.push(ClassItem::Range(start, end));
+        } else {
+            items.push(ClassItem::Char(start));
+        }
+    }
+
+    *position = i;
+    Some(PatternToken::Class { negated, items })
+}
+
+fn class_matches(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Char(expected) => *expected == c,
+        ClassItem::Range(start, end) => (*start..=*end).contains(&c),
+        ClassItem::Named(name) => match name.as_str() {
+            "alnum" => c.is_alphanumeric(),
+            "alpha" => c.is_alphabetic(),
+            "blank" => c == ' ' || c == '\t',
+            "cntrl" => c.is_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => c.is_ascii_graphic(),
+            "lower" => c.is_lowercase(),
+            "print" => c.is_ascii_graphic() || c == ' ',
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_whitespace(),
+            "upper" => c.is_uppercase(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false,
+        },
+    })
+}
+
+/// Matches with a search over the ways each group can match when the pattern
+/// has extended groups, and without backtracking further than the last `*`
+/// otherwise.
+fn match_tokens(tokens: &[PatternToken], text: &[char]) -> bool {
+    if !tokens
+        .iter()
+        .any(|token| matches!(token, PatternToken::Extended { .. }))
+    {
+        return match_simple(tokens, text);
+    }
+
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+
+    match token {
+        PatternToken::Char(c) => text.first() == Some(c) && match_tokens(rest, &text[1..]),
+        PatternToken::AnyChar => !text.is_empty() && match_tokens(rest, &text[1..]),
+        PatternToken::AnyString => (0..=text.len()).any(|i| match_tokens(rest, &text[i..])),
+        PatternToken::Class { negated, items } => text.first().is_some_and(|&c| {
+            class_matches(items, c) != *negated && match_tokens(rest, &text[1..])
+        }),
+        PatternToken::Extended { kind, alternatives } => {
+            match_extended(*kind, alternatives, rest, text)
+        }
+    }
+}
+
+/// Matches a pattern without extended groups. When a later token fails, only
+/// the last `*` needs to take one more character: the ones before it can
+/// already match anything the last one would have taken instead.
+fn match_simple(tokens: &[PatternToken], text: &[char]) -> bool {
+    let (mut t, mut i) = (0, 0);
+    // the token after the last `*` and where in the text that token starts
+    let mut star = None;
+
+    while t < tokens.len() || i < text.len() {
+        match tokens.get(t) {
+            Some(PatternToken::AnyString) => {
+                star = Some((t + 1, i));
+                t += 1;
+                continue;
+            }
+            Some(token) if text.get(i).is_some_and(|&c| token_matches(token, c)) => {
+                t += 1;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match star {
+            Some((after, start)) if start < text.len() => {
+                star = Some((after, start + 1));
+                t = after;
+                i = start + 1;
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+fn token_matches(token: &PatternToken, c: char) -> bool {
+    match token {
+        PatternToken::Char(expected) => *expected == c,
+        PatternToken::AnyChar => true,
+        PatternToken::Class { negated, items } => class_matches(items, c) != *negated,
+        PatternToken::AnyString | PatternToken::Extended { .. } => false,
+    }
+}
+
+fn match_extended(
+    kind: ExtendedKind,
+    alternatives: &[Vec<PatternToken>],
+    rest: &[PatternToken],
+    text: &[char],
+) -> bool {
+    let any_alternative = |s: &[char]| alternatives.iter().any(|alt| match_tokens(alt, s));
+
+    match kind {
+        ExtendedKind::ExactlyOne => {
+            (0..=text.len()).any(|i| any_alternative(&text[..i]) && match_tokens(rest, &text[i..]))
+        }
+        ExtendedKind::ZeroOrOne => {
+            match_tokens(rest, text)
+                || (0..=text.len())
+                    .any(|i| any_alternative(&text[..i]) && match_tokens(rest, &text[i..]))
+        }
+        ExtendedKind::ZeroOrMore => {
+            match_tokens(rest, text)
+                || (1..=text.len()).any(|i| {
+                    any_alternative(&text[..i])
+                        && match_extended(ExtendedKind::ZeroOrMore, alternatives, rest, &text[i..])
+                })
+        }
+        ExtendedKind::OneOrMore => (1..=text.len()).any(|i| {
+            any_alternative(&text[..i])
+                && match_extended(ExtendedKind::ZeroOrMore, alternatives, rest, &text[i..])
+        }),
+        ExtendedKind::Not => {
+            (0..=text.len()).any(|i| !any_alternative(&text[..i]) && match_tokens(rest, &text[i..]))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobOptions {
+    pub dotglob: bool,
+    pub extglob: bool,
+    pub globstar: bool,
+}
+
+/// Expands `pattern` against the file system, returning the sorted list of
+/// matching paths. Wildcards never match a `/`.
+pub fn expand(pattern: &str, options: GlobOptions) -> Vec<String> {
+    let (mut bases, pattern) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components = split_components(pattern);
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+
+        if component.is_empty() {
+            // repeated slashes are kept, a trailing one was already added
+            if !last {
+                bases = bases.into_iter().map(|base| base + "/").collect();
+            }
+            continue;
+        }
+
+        if options.globstar && component == "**" {
+            bases = bases
+                .iter()
+                .flat_map(|base| recursive_entries(base, options.dotglob, last))
+                .collect();
+            continue;
+        }
+
+        let compiled = Pattern::new(component, options.extglob);
+        let separator = if last { "" } else { "/" };
+
+        bases = match compiled.literal() {
+            Some(literal) => bases
+                .into_iter()
+                .map(|base| base + &literal + separator)
+                .collect(),
+            None => bases
+                .iter()
+                .flat_map(|base| {
+                    matching_entries(base, &compiled, options.dotglob, !last)
+                        .into_iter()
+                        .map(move |name| format!("{base}{name}{separator}"))
+                })
+                .collect(),
+        };
+    }
+
+    let mut matches: Vec<String> = bases
+        .into_iter()
+        .filter(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok())
+        .map(|path| {
+            if path.len() > 1 && pattern.ends_with("**") {
+                path.trim_end_matches('/').to_string()
+            } else {
+                path
+            }
+        })
+        .collect();
+
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+fn split_components(pattern: &str) -> Vec<String> {
+    let mut components = vec![String::new()];
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' => components.push(String::new()),
+            '\\' => {
+                let component = components.last_mut().unwrap();
+                component.push('\\');
+                component.extend(chars.next());
+            }
+            c => components.last_mut().unwrap().push(c),
+        }
+    }
+
+    components
+}
+
+fn read_dir_names(base: &str) -> Vec<String> {
+    let dir = if base.is_empty() { "." } else { base };
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn matching_entries(base: &str, pattern: &Pattern, dotglob: bool, dirs_only: bool) -> Vec<String> {
+    let mut names: Vec<String> = read_dir_names(base)
+        .into_iter()
+        .filter(|name| pattern.matches_file_name(name, dotglob))
+        .filter(|name| !dirs_only || is_dir(&format!("{base}{name}")))
+        .collect();
+
+    names.sort();
+    names
+}
+
+/// Everything `**` matches below `base`: `base` itself and every directory
+/// (or, as the last component, every entry) beneath it, without following
+/// symbolic links.
+fn recursive_entries(base: &str, dotglob: bool, include_files: bool) -> Vec<String> {
+    let mut entries = vec![base.to_string()];
+    let mut pending = vec![base.to_string()];
+
+    while let Some(dir) = pending.pop() {
+        let mut names = read_dir_names(&dir);
+        names.sort();
+
+        for name in names {
+            if name.starts_with('.') && !dotglob {
+                continue;
+            }
+
+            let path = format!("{dir}{name}");
+            let is_real_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+
+            if is_real_dir {
+                entries.push(format!("{path}/"));
+                pending.push(format!("{path}/"));
+            } else if include_files {
+                entries.push(path);
+            }
+        }
+    }
+
+    entries
+}
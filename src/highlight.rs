@@ -0,0 +1,169 @@
+use std::fmt::Write as _;
+
+use termion::color;
+
+use crate::{
+    commands::command_exists,
+    shell::Shell,
+    tokenizer::{Token, Tokenizer},
+};
+
+/// Reserved words, after which another command starts.
+const KEYWORDS: [&str; 17] = [
+    "if", "then", "elif", "else", "fi", "do", "done", "while", "until", "for", "case", "esac",
+    "function", "{", "}", "!", "[[",
+];
+
+/// Colors a command line for display, leaving its text unchanged.
+///
+/// Commands are green when they can be run and red otherwise, reserved words
+/// blue, strings yellow, expansions cyan, redirections and control operators
+/// magenta and comments gray.
+pub fn highlight(shell: &Shell, line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut highlighted = String::new();
+    let mut position = 0;
+    let mut command_position = true;
+    let mut after_redirection = false;
+
+    for (token, span) in Tokenizer::tokenize_with_spans(line) {
+        gap(&mut highlighted, &chars[position..span.start]);
+        position = span.end;
+
+        let text: String = chars[span].iter().collect();
+
+        if let Token::Arithmetic(_) = token {
+            paint(&mut highlighted, color::Cyan.fg_str(), &text);
+            command_position = false;
+            continue;
+        }
+
+        let Token::Literal(word) = token else {
+            paint(&mut highlighted, color::Magenta.fg_str(), &text);
+            after_redirection = token.is_redirection();
+            command_position = !after_redirection && !matches!(token, Token::RightParen);
+            continue;
+        };
+
+        if after_redirection || !command_position {
+            after_redirection = false;
+            highlight_word(&mut highlighted, &text);
+            continue;
+        }
+
+        match word.as_literal() {
+            Some(keyword) if KEYWORDS.contains(&keyword) => {
+                paint(&mut highlighted, color::Blue.fg_str(), &text);
+                // `for name` and `case word` are followed by a word, not a command
+                command_position = !matches!(keyword, "for" | "case" | "function" | "[[");
+            }
+            // assignments before a command leave room for the command
+            _ if word.assignment_name().is_some() => highlight_word(&mut highlighted, &text),
+            Some(name) if command_exists(shell, name) => {
+                paint(&mut highlighted, color::Green.fg_str(), &text);
+                command_position = false;
+            }
+            Some(_) => {
+                paint(&mut highlighted, color::Red.fg_str(), &text);
+                command_position = false;
+            }
+            None => {
+                highlight_word(&mut highlighted, &text);
+                command_position = false;
+            }
+        }
+    }
+
+    gap(&mut highlighted, &chars[position.min(chars.len())..]);
+    highlighted
+}
+
+fn paint(highlighted: &mut String, color: &str, text: &str) {
+    write!(highlighted, "{color}{text}{}", color::Reset.fg_str()).unwrap();
+}
+
+/// Writes the text between two tokens: blanks and maybe a comment.
+fn gap(highlighted: &mut String, chars: &[char]) {
+    let text: String = chars.iter().collect();
+
+    match text.find('#') {
+        Some(start) => {
+            highlighted.push_str(&text[..start]);
+            paint(highlighted, color::LightBlack.fg_str(), &text[start..]);
+        }
+        None => highlighted.push_str(&text),
+    }
+}
+
+/// Colors the quoted strings and expansions inside a word.
+fn highlight_word(highlighted: &mut String, text: &str) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let end = match chars[i] {
+            '\\' => (i + 2).min(chars.len()),
+            '\'' => closing(&chars, i + 1, '\''),
+            '"' => closing(&chars, i + 1, '"'),
+            '$' => expansion_end(&chars, i),
+            _ => i + 1,
+        };
+        let part: String = chars[i..end].iter().collect();
+
+        match chars[i] {
+            '\'' | '"' => paint(highlighted, color::Yellow.fg_str(), &part),
+            '$' if end > i + 1 => paint(highlighted, color::Cyan.fg_str(), &part),
+            _ => highlighted.push_str(&part),
+        }
+
+        i = end;
+    }
+}
+
+/// The position after the quote closing a string that starts at `start`, or
+/// the end of the text if the string is still open.
+fn closing(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if quote == '"' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    chars.len()
+}
+
+/// The position after the expansion starting with the `$` at `start`.
+fn expansion_end(chars: &[char], start: usize) -> usize {
+    let (open, close) = match chars.get(start + 1) {
+        Some('{') => ('{', '}'),
+        Some('(') => ('(', ')'),
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            let length = chars[start + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count();
+            return start + 1 + length;
+        }
+        Some(c) if c.is_ascii_digit() || "?$#!@*-".contains(*c) => return start + 2,
+        _ => return start + 1,
+    };
+
+    let mut depth = 0;
+
+    for (i, &c) in chars.iter().enumerate().skip(start + 1) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return i + 1;
+            }
+        }
+    }
+
+    chars.len()
+}
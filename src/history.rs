@@ -1,12 +1,21 @@
 use std::{
+    fmt::Write as _,
     fs::{self, OpenOptions},
     io::Write,
     path::PathBuf,
 };
 
+/// Starts the comment line that records the directory of the entry after it
+/// in a history file.
+const DIRECTORY_PREFIX: &str = "#cwd ";
+
 #[derive(Default)]
 pub struct History {
     commands: Vec<String>,
+    /// The working directory each command was run in, known for the commands
+    /// of this session and for those read from a file written with
+    /// `shopt -s histcwd`.
+    directories: Vec<Option<String>>,
     cursor: Option<usize>,
     append_start: usize,
 }
@@ -15,6 +24,7 @@ impl History {
     pub fn new() -> Self {
         Self {
             commands: Vec::new(),
+            directories: Vec::new(),
             cursor: None,
             append_start: 0,
         }
@@ -51,38 +61,95 @@ impl History {
 
     pub fn add(&mut self, command: String) {
         self.commands.push(command);
+        self.directories.push(None);
         self.cursor = None;
     }
 
+    /// Adds a command along with the directory it was run in.
+    pub fn add_in_directory(&mut self, command: String, directory: String) {
+        self.add(command);
+        *self.directories.last_mut().unwrap() = Some(directory);
+    }
+
+    /// Replaces the most recent command, as `fc` does with itself.
+    pub fn replace_last(&mut self, command: String) {
+        match self.commands.last_mut() {
+            Some(last) => *last = command,
+            None => self.add(command),
+        }
+    }
+
+    /// The most recent command that extends `prefix`, preferring one run in
+    /// `directory`.
+    pub fn suggest(&self, prefix: &str, directory: &str) -> Option<&str> {
+        if prefix.is_empty() {
+            return None;
+        }
+
+        let mut candidates = self
+            .commands
+            .iter()
+            .zip(&self.directories)
+            .rev()
+            .filter(|(command, _)| command.starts_with(prefix) && command.len() > prefix.len());
+
+        let fallback = candidates.clone().next();
+
+        candidates
+            .find(|(_, dir)| dir.as_deref() == Some(directory))
+            .or(fallback)
+            .map(|(command, _)| command.as_str())
+    }
+
     pub fn len(&self) -> usize {
         self.commands.len()
     }
 
     pub fn read_from_file(&mut self, file: PathBuf) {
         let content = fs::read_to_string(file).unwrap();
+        let mut directory = None;
+
         for line in content.lines() {
+            if let Some(dir) = line.strip_prefix(DIRECTORY_PREFIX) {
+                directory = Some(dir.to_string());
+                continue;
+            }
+
             self.add(line.to_string());
+            *self.directories.last_mut().unwrap() = directory.take();
         }
     }
 
-    pub fn write_to_file(&self, file: PathBuf) {
-        fs::write(file, self.commands.join("\n") + "\n").unwrap();
+    /// Writes the whole history to `file`, with the directory of each entry
+    /// on a comment line before it if `directories` is set.
+    pub fn write_to_file(&self, file: PathBuf, directories: bool) {
+        fs::write(file, self.file_lines(0, directories)).unwrap();
     }
 
-    pub fn append_to_file(&mut self, file: PathBuf) {
+    pub fn append_to_file(&mut self, file: PathBuf, directories: bool) {
         let mut file = OpenOptions::new().append(true).open(file).unwrap();
 
-        write!(
-            file,
-            "{}",
-            self.commands[self.append_start..]
-                .iter()
-                .fold(String::new(), |acc, x| acc + x + "\n")
-        )
-        .unwrap();
+        write!(file, "{}", self.file_lines(self.append_start, directories)).unwrap();
 
         self.append_start = self.commands.len();
     }
+
+    /// The lines of a history file for the entries from `start` on.
+    fn file_lines(&self, start: usize, directories: bool) -> String {
+        let mut lines = String::new();
+
+        for (command, directory) in self.commands[start..]
+            .iter()
+            .zip(&self.directories[start..])
+        {
+            if let (true, Some(directory)) = (directories, directory) {
+                writeln!(lines, "{DIRECTORY_PREFIX}{directory}").unwrap();
+            }
+            writeln!(lines, "{command}").unwrap();
+        }
+
+        lines
+    }
 }
 
 impl<'a> IntoIterator for &'a History {
@@ -0,0 +1,511 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use termion::event::{self, Event, Key};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BindError {
+    #[error("{0}: unknown function name")]
+    UnknownFunction(String),
+    #[error("{0}: invalid key sequence")]
+    KeySequence(String),
+    #[error("`{0}': missing colon separator")]
+    MissingColon(String),
+    #[error("{0}: unknown variable name")]
+    UnknownVariable(String),
+    #[error("{0}: invalid value")]
+    InvalidValue(String),
+}
+
+/// The line editor functions keys can be bound to, named as in readline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    AcceptLine,
+    Complete,
+    BackwardDeleteChar,
+    DeleteChar,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    BeginningOfLine,
+    EndOfLine,
+    PreviousHistory,
+    NextHistory,
+    EditAndExecuteCommand,
+    ReReadInitFile,
+}
+
+const ACTIONS: [(&str, Action); 14] = [
+    ("accept-line", Action::AcceptLine),
+    ("complete", Action::Complete),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("delete-char", Action::DeleteChar),
+    ("backward-char", Action::BackwardChar),
+    ("forward-char", Action::ForwardChar),
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("previous-history", Action::PreviousHistory),
+    ("next-history", Action::NextHistory),
+    ("edit-and-execute-command", Action::EditAndExecuteCommand),
+    ("re-read-init-file", Action::ReReadInitFile),
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(_, action)| *action == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| *action == name)
+            .map(|(_, action)| *action)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        ACTIONS.iter().map(|(name, _)| *name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Action(Action),
+    /// Text inserted at the cursor.
+    Macro(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BellStyle {
+    None,
+    Visible,
+    Audible,
+}
+
+pub enum Lookup<'a> {
+    Bound(&'a Binding),
+    /// The keys start a longer bound sequence.
+    Prefix,
+    Unbound,
+}
+
+/// The key bindings and settings of the line editor, set by the `bind`
+/// builtin and inputrc files.
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Binding>,
+    pub completion_ignore_case: bool,
+    pub bell_style: BellStyle,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let defaults = [
+            (vec![Key::Char('\n')], Action::AcceptLine),
+            (vec![Key::Char('\t')], Action::Complete),
+            (vec![Key::Backspace], Action::BackwardDeleteChar),
+            (vec![Key::Ctrl('h')], Action::BackwardDeleteChar),
+            (vec![Key::Delete], Action::DeleteChar),
+            (vec![Key::Left], Action::BackwardChar),
+            (vec![Key::Ctrl('b')], Action::BackwardChar),
+            (vec![Key::Right], Action::ForwardChar),
+            (vec![Key::Ctrl('f')], Action::ForwardChar),
+            (vec![Key::Alt('b')], Action::BackwardWord),
+            (vec![Key::Alt('f')], Action::ForwardWord),
+            (vec![Key::Home], Action::BeginningOfLine),
+            (vec![Key::Ctrl('a')], Action::BeginningOfLine),
+            (vec![Key::End], Action::EndOfLine),
+            (vec![Key::Ctrl('e')], Action::EndOfLine),
+            (vec![Key::Up], Action::PreviousHistory),
+            (vec![Key::Ctrl('p')], Action::PreviousHistory),
+            (vec![Key::Down], Action::NextHistory),
+            (vec![Key::Ctrl('n')], Action::NextHistory),
+            (
+                vec![Key::Ctrl('x'), Key::Ctrl('e')],
+                Action::EditAndExecuteCommand,
+            ),
+            (vec![Key::Ctrl('x'), Key::Ctrl('r')], Action::ReReadInitFile),
+        ];
+
+        Self {
+            bindings: defaults
+                .into_iter()
+                .map(|(keys, action)| (keys, Binding::Action(action)))
+                .collect(),
+            completion_ignore_case: false,
+            bell_style: BellStyle::Audible,
+        }
+    }
+}
+
+impl Keymap {
+    /// What the keys typed so far are bound to. A sequence that starts a
+    /// longer one waits for the rest of it.
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_> {
+        if self
+            .bindings
+            .keys()
+            .any(|sequence| sequence.len() > keys.len() && sequence.starts_with(keys))
+        {
+            return Lookup::Prefix;
+        }
+
+        match self.bindings.get(keys) {
+            Some(binding) => Lookup::Bound(binding),
+            None => Lookup::Unbound,
+        }
+    }
+
+    /// The binding of exactly `keys`, even if they also start a longer
+    /// sequence.
+    pub fn binding(&self, keys: &[Key]) -> Option<&Binding> {
+        self.bindings.get(keys)
+    }
+
+    /// Reads the lines of an inputrc file, returning a message for each line
+    /// that could not be read.
+    pub fn read_file(&mut self, path: &Path) -> io::Result<Vec<String>> {
+        let source = fs::read_to_string(path)?;
+
+        Ok(source
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                self.parse_line(line)
+                    .err()
+                    .map(|e| format!("{}: line {}: {e}", path.display(), i + 1))
+            })
+            .collect())
+    }
+
+    /// Applies a line of inputrc: `"keyseq": function-name`, `keyname:
+    /// function-name`, either bound to a `"macro"` instead, or `set variable
+    /// value`.
+    pub fn parse_line(&mut self, line: &str) -> Result<(), BindError> {
+        let line = line.trim();
+
+        // conditional constructs are not supported, so every branch applies
+        if line.is_empty() || line.starts_with('#') || line.starts_with('$') {
+            return Ok(());
+        }
+
+        if let Some(rest) = line
+            .strip_prefix("set")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+        {
+            let mut words = rest.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            return self.set(name, words.next().unwrap_or_default());
+        }
+
+        let (keys, rest) = if let Some(quoted) = line.strip_prefix('"') {
+            let end = closing_quote(quoted, '"')
+                .ok_or_else(|| BindError::MissingColon(line.to_string()))?;
+            (key_sequence(&quoted[..end])?, &quoted[end + 1..])
+        } else {
+            let colon = line
+                .find(':')
+                .ok_or_else(|| BindError::MissingColon(line.to_string()))?;
+            (key_name(line[..colon].trim())?, &line[colon..])
+        };
+
+        let value = rest
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| BindError::MissingColon(line.to_string()))?
+            .trim();
+
+        let binding = if let Some(quote @ ('"' | '\'')) = value.chars().next() {
+            let text = &value[1..];
+            let end = closing_quote(text, quote).unwrap_or(text.len());
+            Binding::Macro(String::from_utf8_lossy(&unescape(&text[..end])).into_owned())
+        } else {
+            let name = value.split_whitespace().next().unwrap_or_default();
+            let action = Action::from_name(name)
+                .ok_or_else(|| BindError::UnknownFunction(name.to_string()))?;
+            Binding::Action(action)
+        };
+
+        self.bindings.insert(keys, binding);
+        Ok(())
+    }
+
+    pub fn unbind(&mut self, sequence: &str) -> Result<(), BindError> {
+        self.bindings.remove(&key_sequence(sequence)?);
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), BindError> {
+        match name {
+            "completion-ignore-case" => {
+                self.completion_ignore_case =
+                    value.eq_ignore_ascii_case("on") || value == "1" || value.is_empty();
+            }
+            "bell-style" => {
+                self.bell_style = match value {
+                    "none" => BellStyle::None,
+                    "visible" => BellStyle::Visible,
+                    "audible" | "on" => BellStyle::Audible,
+                    _ => return Err(BindError::InvalidValue(value.to_string())),
+                };
+            }
+            name => return Err(BindError::UnknownVariable(name.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// The bindings as inputrc lines.
+    pub fn bindings(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .bindings
+            .iter()
+            .filter_map(|(keys, binding)| {
+                let sequence = keys
+                    .iter()
+                    .map(|key| key_text(*key))
+                    .collect::<Option<String>>()?;
+
+                Some(match binding {
+                    Binding::Action(action) => format!("\"{sequence}\": {}", action.name()),
+                    Binding::Macro(text) => {
+                        let text: Option<String> =
+                            text.chars().map(|c| key_text(Key::Char(c))).collect();
+                        format!("\"{sequence}\": \"{}\"", text.unwrap_or_default())
+                    }
+                })
+            })
+            .collect();
+
+        lines.sort();
+        lines
+    }
+
+    /// The settings as inputrc lines.
+    pub fn variables(&self) -> Vec<String> {
+        let bell_style = match self.bell_style {
+            BellStyle::None => "none",
+            BellStyle::Visible => "visible",
+            BellStyle::Audible => "audible",
+        };
+        let ignore_case = if self.completion_ignore_case {
+            "on"
+        } else {
+            "off"
+        };
+
+        vec![
+            format!("set bell-style {bell_style}"),
+            format!("set completion-ignore-case {ignore_case}"),
+        ]
+    }
+}
+
+/// The position of the first `quote` not escaped by a backslash.
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Decodes the escapes of a key sequence or macro: `\C-x` and `\M-x` for
+/// control and meta keys, `\e` for escape, and those of C strings.
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        let Some(escape) = chars.next() else {
+            bytes.push(b'\\');
+            break;
+        };
+
+        match escape {
+            'C' if chars.next_if_eq(&'-').is_some() => {
+                if let Some(c) = chars.next() {
+                    bytes.push(control(c));
+                }
+            }
+            // a meta key is sent as escape followed by the key
+            'M' if chars.next_if_eq(&'-').is_some() => bytes.push(0x1b),
+            'e' => bytes.push(0x1b),
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'd' => bytes.push(0x7f),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else {
+                        break;
+                    };
+                    chars.next();
+                    code = code * 8 + digit;
+                }
+                bytes.push(u8::try_from(code & 0xff).unwrap());
+            }
+            'x' => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) else {
+                        break;
+                    };
+                    chars.next();
+                    code = code * 16 + digit;
+                }
+                bytes.push(u8::try_from(code).unwrap());
+            }
+            escape => bytes.extend_from_slice(escape.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    bytes
+}
+
+fn control(c: char) -> u8 {
+    match c {
+        '?' => 0x7f,
+        c => u8::try_from(c.to_ascii_lowercase()).map_or(0, |c| c & 0x1f),
+    }
+}
+
+/// Turns the text of a key sequence into keys the way the terminal input is
+/// read, so that `"\e[A"` is the up arrow.
+fn key_sequence(text: &str) -> Result<Vec<Key>, BindError> {
+    let bytes = unescape(text);
+    let mut rest = bytes.as_slice();
+    let mut keys = Vec::new();
+
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == 0x1b && after.first() == Some(&b'[') && !is_key_csi(&after[1..]) {
+            return Err(BindError::KeySequence(text.to_string()));
+        }
+
+        let mut after = after.iter().map(|&byte| Ok(byte));
+        match event::parse_event(byte, &mut after) {
+            Ok(Event::Key(key)) => keys.push(key),
+            Err(_) if byte == 0x1b => keys.push(Key::Esc),
+            _ => return Err(BindError::KeySequence(text.to_string())),
+        }
+        rest = &rest[rest.len() - after.len()..];
+    }
+
+    if keys.is_empty() {
+        return Err(BindError::KeySequence(text.to_string()));
+    }
+
+    Ok(keys)
+}
+
+/// Whether `bytes`, which follow `ESC [`, start a control sequence for a key
+/// that termion parses without panicking, as it does on mouse reports, on
+/// sequences cut short and on parameters that aren't numbers below 256.
+fn is_key_csi(bytes: &[u8]) -> bool {
+    let Some(end) = bytes.iter().position(|byte| (0x40..=0x7e).contains(byte)) else {
+        return false;
+    };
+    let parameters = &bytes[..end];
+
+    bytes[end] != b'M'
+        && (parameters.is_empty()
+            || parameters
+                .split(|&byte| byte == b';')
+                .all(|number| str::from_utf8(number).is_ok_and(|n| n.parse::<u8>().is_ok())))
+}
+
+/// Turns a key name such as `Control-u`, `M-f` or `Rubout` into a key.
+fn key_name(name: &str) -> Result<Vec<Key>, BindError> {
+    let mut rest = name;
+    let mut escaped = String::new();
+
+    loop {
+        if let Some(key) = rest
+            .strip_prefix("Control-")
+            .or_else(|| rest.strip_prefix("C-"))
+        {
+            escaped.push_str("\\C-");
+            rest = key;
+        } else if let Some(key) = rest
+            .strip_prefix("Meta-")
+            .or_else(|| rest.strip_prefix("M-"))
+        {
+            escaped.push_str("\\M-");
+            rest = key;
+        } else {
+            break;
+        }
+    }
+
+    let key = match rest.to_ascii_lowercase().as_str() {
+        "rubout" | "del" => "?",
+        "escape" | "esc" => "\\e",
+        "lfd" | "newline" => "\\n",
+        "return" | "ret" => "\\r",
+        "space" | "spc" => " ",
+        "tab" => "\\t",
+        _ if rest.chars().count() == 1 => rest,
+        _ => return Err(BindError::KeySequence(name.to_string())),
+    };
+
+    // a lone `?` is only Rubout after `\C-`
+    if key == "?" && !escaped.ends_with("\\C-") {
+        escaped.push_str("\\d");
+    } else {
+        escaped.push_str(key);
+    }
+
+    key_sequence(&escaped)
+}
+
+/// Writes a key back the way a key sequence is read.
+fn key_text(key: Key) -> Option<String> {
+    Some(match key {
+        Key::Char('\n') => "\\C-m".to_string(),
+        Key::Char('\t') => "\\C-i".to_string(),
+        Key::Char(c @ ('"' | '\\')) => format!("\\{c}"),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("\\C-{c}"),
+        Key::Alt(c) => format!("\\e{}", key_text(Key::Char(c))?),
+        Key::Backspace => "\\C-?".to_string(),
+        Key::Esc => "\\e".to_string(),
+        Key::Up => "\\e[A".to_string(),
+        Key::Down => "\\e[B".to_string(),
+        Key::Right => "\\e[C".to_string(),
+        Key::Left => "\\e[D".to_string(),
+        Key::Home => "\\e[H".to_string(),
+        Key::End => "\\e[F".to_string(),
+        Key::BackTab => "\\e[Z".to_string(),
+        Key::Insert => "\\e[2~".to_string(),
+        Key::Delete => "\\e[3~".to_string(),
+        Key::PageUp => "\\e[5~".to_string(),
+        Key::PageDown => "\\e[6~".to_string(),
+        Key::F(n @ 1..=4) => format!("\\eO{}", char::from(b'P' + n - 1)),
+        Key::F(n @ 5) => format!("\\e[{}~", n + 10),
+        Key::F(n @ 6..=10) => format!("\\e[{}~", n + 11),
+        Key::F(n @ 11..=12) => format!("\\e[{}~", n + 12),
+        _ => return None,
+    })
+}
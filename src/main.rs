@@ -1,15 +1,62 @@
 #![warn(clippy::pedantic)]
 
-use crate::shell::Shell;
+use std::{
+    fs,
+    io::{self, IsTerminal, Read},
+    process,
+};
 
+use crate::{shell::Shell, startup::Invocation};
+
+mod arith;
+mod brace;
 mod commands;
+mod condition;
 mod eval;
+mod expand;
+mod git;
+mod glob;
+mod highlight;
 mod history;
+mod keymap;
+mod options;
+mod param;
 mod parser;
+mod prompt;
 mod shell;
+mod startup;
+mod stdio;
 mod tokenizer;
+mod users;
+mod variables;
 
 fn main() {
+    let invocation = Invocation::parse(std::env::args()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(2);
+    });
+
     let mut shell = Shell::new();
+    let mut args = invocation.args.iter().cloned();
+    let interactive = invocation.args.is_empty() && io::stdin().is_terminal();
+
+    startup::load(&mut shell, &invocation, interactive);
+
+    if let Some(path) = args.next() {
+        let source = fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            process::exit(127);
+        });
+        shell.set_arg0(path);
+        shell.set_positional(args.collect());
+        process::exit(shell.run_script(&source));
+    }
+
+    if !interactive {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).unwrap();
+        process::exit(shell.run_script(&source));
+    }
+
     shell.run();
 }
@@ -0,0 +1,42 @@
+use crate::glob::GlobOptions;
+
+/// Shell behaviour toggled with the `shopt` builtin.
+#[derive(Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+    pub dotglob: bool,
+    pub extglob: bool,
+    pub failglob: bool,
+    pub globstar: bool,
+    /// Records the working directory of each entry when writing history.
+    pub histcwd: bool,
+    pub nullglob: bool,
+    /// Set with `set -x` rather than `shopt`.
+    pub xtrace: bool,
+}
+
+impl Options {
+    pub const NAMES: [&str; 6] = [
+        "dotglob", "extglob", "failglob", "globstar", "histcwd", "nullglob",
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "extglob" => Some(&mut self.extglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "histcwd" => Some(&mut self.histcwd),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+
+    pub fn glob(&self) -> GlobOptions {
+        GlobOptions {
+            dotglob: self.dotglob,
+            extglob: self.extglob,
+            globstar: self.globstar,
+        }
+    }
+}
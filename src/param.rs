@@ -0,0 +1,497 @@
+use std::process;
+
+use crate::{
+    expand::{self, ExpansionError},
+    glob::Pattern,
+    shell::Shell,
+    tokenizer::{Tokenizer, Word, is_name},
+};
+
+/// The result of a parameter expansion.
+pub enum Expanded {
+    /// A value computed from the parameter.
+    Text(String),
+    /// An operand word that replaces the expansion, such as the `x` in
+    /// `${v:-x}`, still to be expanded in the surrounding context.
+    Operand(Word),
+}
+
+/// Expands the text of a `$name` or `${...}` parameter expansion.
+pub fn expand(shell: &mut Shell, text: &str) -> Result<Expanded, ExpansionError> {
+    let bad_substitution = || ExpansionError::BadSubstitution(text.to_string());
+
+    if let Some(name) = text.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if parameter_name_length(name) != name.len() {
+            return Err(bad_substitution());
+        }
+
+        let length = match name.strip_suffix("[@]").or(name.strip_suffix("[*]")) {
+            Some(array) => shell.variables().elements(array).len(),
+            None if name == "@" || name == "*" => shell.positional().len(),
+            None => lookup(shell, name).unwrap_or_default().chars().count(),
+        };
+        return Ok(Expanded::Text(length.to_string()));
+    }
+
+    let (name, operation) = if let Some(rest) = text.strip_prefix('!').filter(|r| !r.is_empty()) {
+        // indirection: the value of the named parameter is the name to use
+        let length = parameter_name_length(rest);
+        let target = lookup(shell, &rest[..length]).unwrap_or_default();
+
+        if parameter_name_length(&target) != target.len() {
+            return Err(bad_substitution());
+        }
+
+        (target, &rest[length..])
+    } else {
+        let length = parameter_name_length(text);
+        (text[..length].to_string(), &text[length..])
+    };
+
+    if name.is_empty() {
+        return Err(bad_substitution());
+    }
+
+    let Some((operator, rest)) = Operator::parse(operation) else {
+        return Err(bad_substitution());
+    };
+
+    apply(shell, &name, operator, rest, text)
+}
+
+fn apply(
+    shell: &mut Shell,
+    name: &str,
+    operator: Operator,
+    rest: &str,
+    text: &str,
+) -> Result<Expanded, ExpansionError> {
+    let value = lookup(shell, name);
+
+    let operand = |shell: &mut Shell| -> Result<String, ExpansionError> {
+        expand::expand_to_string(shell, &Tokenizer::operand(rest))
+    };
+    let pattern = |shell: &mut Shell, text: &str| -> Result<Pattern, ExpansionError> {
+        let pattern = expand::expand_to_pattern(shell, &Tokenizer::operand(text))?;
+        Ok(Pattern::new(&pattern, shell.options().extglob))
+    };
+
+    let is_null = |value: &Option<String>, colon: bool| match value {
+        None => true,
+        Some(value) => colon && value.is_empty(),
+    };
+
+    let operand_word = || Tokenizer::operand(rest);
+
+    Ok(match operator {
+        Operator::None => Expanded::Text(value.unwrap_or_default()),
+        Operator::Default { colon } => match value {
+            ref v if is_null(v, colon) => Expanded::Operand(operand_word()),
+            value => Expanded::Text(value.unwrap_or_default()),
+        },
+        Operator::Alternative { colon } => {
+            if is_null(&value, colon) {
+                Expanded::Text(String::new())
+            } else {
+                Expanded::Operand(operand_word())
+            }
+        }
+        Operator::Assign { colon } => match value {
+            ref v if is_null(v, colon) => {
+                if !is_name(name) {
+                    return Err(ExpansionError::Parameter(
+                        format!("${name}"),
+                        String::from("cannot assign in this way"),
+                    ));
+                }
+
+                let value = operand(shell)?;
+                shell.variables_mut().set(name, value.clone());
+                Expanded::Text(value)
+            }
+            value => Expanded::Text(value.unwrap_or_default()),
+        },
+        Operator::Error { colon } => match value {
+            ref v if is_null(v, colon) => {
+                let mut message = operand(shell)?;
+                if message.is_empty() {
+                    message = String::from("parameter null or not set");
+                }
+                let error = ExpansionError::Parameter(name.to_string(), message);
+
+                // a shell that isn't interactive gives up on the script
+                if !shell.interactive() {
+                    eprintln!("{error}");
+                    process::exit(127);
+                }
+                return Err(error);
+            }
+            value => Expanded::Text(value.unwrap_or_default()),
+        },
+        Operator::RemovePrefix { longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = pattern(shell, rest)?;
+            Expanded::Text(remove_prefix(&value, &pattern, longest))
+        }
+        Operator::RemoveSuffix { longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = pattern(shell, rest)?;
+            Expanded::Text(remove_suffix(&value, &pattern, longest))
+        }
+        Operator::Replace { mode } => {
+            let value = value.unwrap_or_default();
+            let (pattern_text, replacement) = split_replacement(rest);
+            let pattern = pattern(shell, pattern_text)?;
+            let replacement = match replacement {
+                Some(replacement) => {
+                    expand::expand_to_string(shell, &Tokenizer::operand(replacement))?
+                }
+                None => String::new(),
+            };
+            Expanded::Text(replace(&value, &pattern, &replacement, mode))
+        }
+        Operator::Substring => {
+            let value = value.unwrap_or_default();
+            let (offset, length) = match rest.split_once(':') {
+                Some((offset, length)) => (offset, Some(length)),
+                None => (rest, None),
+            };
+
+            let offset = expand::arithmetic(shell, offset)?;
+            let length = length
+                .map(|length| expand::arithmetic(shell, length))
+                .transpose()?;
+
+            Expanded::Text(substring(&value, offset, length, text)?)
+        }
+        Operator::Case { upper, all } => {
+            let value = value.unwrap_or_default();
+            let pattern = pattern(shell, if rest.is_empty() { "?" } else { rest })?;
+            Expanded::Text(change_case(&value, &pattern, upper, all))
+        }
+    })
+}
+
+/// The value of a variable or special parameter.
+pub fn lookup(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(shell.status().to_string()),
+        "$" => Some(shell.pid().to_string()),
+        "0" => Some(shell.arg0().to_string()),
+        "#" => Some(shell.positional().len().to_string()),
+        "@" => Some(shell.positional().join(" ")),
+        "*" => {
+            let separator = match shell.variables().get("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => String::from(" "),
+            };
+            Some(shell.positional().join(&separator))
+        }
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            let index: usize = name.parse().ok()?;
+            shell.positional().get(index.checked_sub(1)?).cloned()
+        }
+        name => match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            Some((array, "@" | "*")) => Some(shell.variables().elements(array).join(" ")),
+            Some((array, index)) => {
+                let index: usize = index.trim().parse().ok()?;
+                shell
+                    .variables()
+                    .elements(array)
+                    .get(index)
+                    .map(|element| (*element).to_string())
+            }
+            None => shell.variables().get(name).map(String::from),
+        },
+    }
+}
+
+/// The length of the parameter name at the start of `text`.
+fn parameter_name_length(text: &str) -> usize {
+    let Some(first) = text.chars().next() else {
+        return 0;
+    };
+
+    if first.is_ascii_digit() {
+        return text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+    }
+
+    if "?$#!@*-".contains(first) {
+        return 1;
+    }
+
+    let length = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len());
+
+    // an array subscript is part of the name
+    match text[length..]
+        .strip_prefix('[')
+        .and_then(|rest| rest.find(']'))
+    {
+        Some(end) => length + end + 2,
+        None => length,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    None,
+    Default { colon: bool },
+    Assign { colon: bool },
+    Error { colon: bool },
+    Alternative { colon: bool },
+    RemovePrefix { longest: bool },
+    RemoveSuffix { longest: bool },
+    Replace { mode: ReplaceMode },
+    Substring,
+    Case { upper: bool, all: bool },
+}
+
+impl Operator {
+    const SYMBOLS: [(&str, Operator); 21] = [
+        (":-", Operator::Default { colon: true }),
+        (":=", Operator::Assign { colon: true }),
+        (":?", Operator::Error { colon: true }),
+        (":+", Operator::Alternative { colon: true }),
+        ("-", Operator::Default { colon: false }),
+        ("=", Operator::Assign { colon: false }),
+        ("?", Operator::Error { colon: false }),
+        ("+", Operator::Alternative { colon: false }),
+        ("##", Operator::RemovePrefix { longest: true }),
+        ("#", Operator::RemovePrefix { longest: false }),
+        ("%%", Operator::RemoveSuffix { longest: true }),
+        ("%", Operator::RemoveSuffix { longest: false }),
+        (
+            "//",
+            Operator::Replace {
+                mode: ReplaceMode::All,
+            },
+        ),
+        (
+            "/#",
+            Operator::Replace {
+                mode: ReplaceMode::Prefix,
+            },
+        ),
+        (
+            "/%",
+            Operator::Replace {
+                mode: ReplaceMode::Suffix,
+            },
+        ),
+        (
+            "/",
+            Operator::Replace {
+                mode: ReplaceMode::First,
+            },
+        ),
+        (":", Operator::Substring),
+        (
+            "^^",
+            Operator::Case {
+                upper: true,
+                all: true,
+            },
+        ),
+        (
+            "^",
+            Operator::Case {
+                upper: true,
+                all: false,
+            },
+        ),
+        (
+            ",,",
+            Operator::Case {
+                upper: false,
+                all: true,
+            },
+        ),
+        (
+            ",",
+            Operator::Case {
+                upper: false,
+                all: false,
+            },
+        ),
+    ];
+
+    /// Splits the operation following the parameter name into the operator
+    /// and the rest of the text.
+    fn parse(operation: &str) -> Option<(Self, &str)> {
+        if operation.is_empty() {
+            return Some((Operator::None, operation));
+        }
+
+        Self::SYMBOLS.iter().find_map(|(symbol, operator)| {
+            operation.strip_prefix(symbol).map(|rest| (*operator, rest))
+        })
+    }
+}
+
+/// Splits `pattern/replacement` at the first unescaped, unquoted slash.
+fn split_replacement(text: &str) -> (&str, Option<&str>) {
+    let mut chars = text.char_indices();
+    let mut quote = None;
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' | '"' if quote == Some(c) => quote = None,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            '/' if quote.is_none() => return (&text[..i], Some(&text[i + 1..])),
+            _ => {}
+        }
+    }
+
+    (text, None)
+}
+
+fn char_boundaries(value: &str) -> Vec<usize> {
+    value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(value.len()))
+        .collect()
+}
+
+fn remove_prefix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let boundaries = char_boundaries(value);
+
+    let matching = |&&i: &&usize| pattern.matches(&value[..i]);
+    let end = if longest {
+        boundaries.iter().rev().find(matching)
+    } else {
+        boundaries.iter().find(matching)
+    };
+
+    end.map_or_else(|| value.to_string(), |&end| value[end..].to_string())
+}
+
+fn remove_suffix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let boundaries = char_boundaries(value);
+
+    let matching = |&&i: &&usize| pattern.matches(&value[i..]);
+    let start = if longest {
+        boundaries.iter().find(matching)
+    } else {
+        boundaries.iter().rev().find(matching)
+    };
+
+    start.map_or_else(|| value.to_string(), |&start| value[..start].to_string())
+}
+
+fn replace(value: &str, pattern: &Pattern, replacement: &str, mode: ReplaceMode) -> String {
+    let boundaries = char_boundaries(value);
+
+    match mode {
+        ReplaceMode::Prefix => boundaries
+            .iter()
+            .rev()
+            .find(|&&end| pattern.matches(&value[..end]))
+            .map_or_else(
+                || value.to_string(),
+                |&end| format!("{replacement}{}", &value[end..]),
+            ),
+        ReplaceMode::Suffix => boundaries
+            .iter()
+            .find(|&&start| pattern.matches(&value[start..]))
+            .map_or_else(
+                || value.to_string(),
+                |&start| format!("{}{replacement}", &value[..start]),
+            ),
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut result = String::new();
+            let mut i = 0;
+
+            while i < boundaries.len() {
+                let start = boundaries[i];
+                let end = boundaries[i..]
+                    .iter()
+                    .rev()
+                    .find(|&&end| end > start && pattern.matches(&value[start..end]));
+
+                if let Some(&end) = end {
+                    result.push_str(replacement);
+
+                    if mode == ReplaceMode::First {
+                        result.push_str(&value[end..]);
+                        return result;
+                    }
+
+                    i = boundaries.iter().position(|&b| b == end).unwrap();
+                } else {
+                    if let Some(&next) = boundaries.get(i + 1) {
+                        result.push_str(&value[start..next]);
+                    }
+                    i += 1;
+                }
+            }
+
+            result
+        }
+    }
+}
+
+fn substring(
+    value: &str,
+    offset: i64,
+    length: Option<i64>,
+    text: &str,
+) -> Result<String, ExpansionError> {
+    let chars: Vec<char> = value.chars().collect();
+    let count = i64::try_from(chars.len()).unwrap();
+
+    let start = if offset < 0 { count + offset } else { offset };
+    if start < 0 || start > count {
+        return Ok(String::new());
+    }
+
+    let end = match length {
+        None => count,
+        Some(length) if length < 0 => count + length,
+        Some(length) => (start + length).min(count),
+    };
+
+    if end < start {
+        return Err(ExpansionError::Parameter(
+            text.to_string(),
+            String::from("substring expression < 0"),
+        ));
+    }
+
+    let start = usize::try_from(start).unwrap();
+    let end = usize::try_from(end).unwrap();
+    Ok(chars[start..end].iter().collect())
+}
+
+fn change_case(value: &str, pattern: &Pattern, upper: bool, all: bool) -> String {
+    let mut result = String::new();
+
+    for (i, c) in value.chars().enumerate() {
+        if (all || i == 0) && pattern.matches(&c.to_string()) {
+            if upper {
+                result.extend(c.to_uppercase());
+            } else {
+                result.extend(c.to_lowercase());
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
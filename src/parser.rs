@@ -1,25 +1,117 @@
-use crate::tokenizer::Token;
+use std::{collections::HashMap, rc::Rc};
+
+use thiserror::Error;
+
+use crate::tokenizer::{Segment, Token, Tokenizer, Word, is_name};
+
+/// Reserved words that close the command list of a compound command.
+const CLOSING_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
 
 #[derive(Debug)]
 pub enum Expr {
     Command {
-        name: String,
-        args: Vec<String>,
+        assignments: Vec<Word>,
+        words: Vec<Word>,
     },
     Redirect {
         src: Box<Expr>,
         stream: Stream,
-        dest: String,
+        dest: Word,
     },
     Append {
         src: Box<Expr>,
         stream: Stream,
-        dest: String,
+        dest: Word,
     },
     Pipe {
         src: Box<Expr>,
         dest: Box<Expr>,
     },
+    Arithmetic(String),
+    List(Vec<Expr>),
+    And {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Or {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Not(Box<Expr>),
+    If {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Option<Box<Expr>>,
+    },
+    While {
+        condition: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Until {
+        condition: Box<Expr>,
+        body: Box<Expr>,
+    },
+    For {
+        name: String,
+        words: Vec<Word>,
+        body: Box<Expr>,
+    },
+    ArithmeticFor {
+        init: String,
+        condition: String,
+        update: String,
+        body: Box<Expr>,
+    },
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+    Function {
+        name: String,
+        body: Rc<Expr>,
+    },
+    Group(Box<Expr>),
+    Subshell(Box<Expr>),
+    /// A `[[ ... ]]` conditional expression.
+    Conditional(Condition),
+}
+
+/// The expression inside `[[ ... ]]`, whose words are expanded without field
+/// splitting or pathname expansion.
+#[derive(Debug)]
+pub enum Condition {
+    Unary {
+        operator: String,
+        operand: Word,
+    },
+    Binary {
+        operator: String,
+        left: Word,
+        right: Word,
+    },
+    /// A lone word, which is true if it expands to a non-empty string.
+    Word(Word),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: Option<Expr>,
+    pub terminator: CaseTerminator,
+}
+
+/// What happens after the body of a matching `case` item has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;` ends the `case` command.
+    Break,
+    /// `;&` runs the body of the next item as well.
+    FallThrough,
+    /// `;;&` goes on testing the patterns of the next items.
+    Continue,
 }
 
 #[derive(Debug)]
@@ -29,21 +121,46 @@ pub enum Stream {
     Stderr,
 }
 
-pub struct Parser {
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    /// The input ended in the middle of a command, so more lines are needed.
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+}
+
+pub struct Parser<'a> {
     tokens: Vec<Token>,
     position: usize,
-    ast: Option<Expr>,
+    aliases: &'a HashMap<String, String>,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Token>, aliases: &'a HashMap<String, String>) -> Self {
+        Self::resume(tokens, 0, aliases)
+    }
+
+    /// Goes on parsing `tokens` from `position`, as returned by
+    /// `into_tokens`, with what may be a changed set of aliases.
+    pub fn resume(
+        tokens: Vec<Token>,
+        position: usize,
+        aliases: &'a HashMap<String, String>,
+    ) -> Self {
         Self {
             tokens,
-            position: 0,
-            ast: None,
+            position,
+            aliases,
         }
     }
 
+    /// The tokens with the aliases expanded so far, and how far they were
+    /// parsed.
+    pub fn into_tokens(self) -> (Vec<Token>, usize) {
+        (self.tokens, self.position)
+    }
+
     fn next(&mut self) -> Option<Token> {
         let t = self.tokens.get(self.position).cloned();
         self.position += 1;
@@ -54,90 +171,805 @@ impl Parser {
         self.tokens.get(self.position).cloned()
     }
 
-    pub fn parse(&mut self) {
-        self.ast = Some(self.command());
+    /// The next token if it is a plain unquoted word, which is how reserved
+    /// words are recognised when they appear in command position.
+    fn peek_word(&self) -> Option<&str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Literal(word)) => word.as_literal(),
+            _ => None,
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.tokens.get(self.position) {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedEof,
+        }
+    }
+
+    fn expect(&mut self, reserved_word: &str) -> Result<(), ParseError> {
+        if self.peek_word() != Some(reserved_word) {
+            return Err(self.unexpected());
+        }
+
+        self.next();
+        Ok(())
+    }
+
+    /// Parses the whole input, returning `None` if it holds no command.
+    pub fn parse(&mut self) -> Result<Option<Expr>, ParseError> {
+        self.linebreak();
+
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+
+        let list = self.list()?;
+
+        match self.peek() {
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Ok(Some(list)),
+        }
+    }
+
+    /// Parses the commands up to the end of the next line that completes
+    /// one, returning `None` at the end of the input. Running them before
+    /// parsing further lets an alias they define apply from the next line.
+    pub fn complete_command(&mut self) -> Result<Option<Expr>, ParseError> {
+        self.linebreak();
+
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+
+        let mut commands = vec![self.and_or()?];
+
+        while let Some(Token::Semicolon) = self.peek() {
+            self.next();
+
+            if matches!(self.peek(), None | Some(Token::Newline)) {
+                break;
+            }
+
+            commands.push(self.and_or()?);
+        }
+
+        match self.peek() {
+            None | Some(Token::Newline) => {}
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        }
+
+        if commands.len() == 1 {
+            return Ok(commands.pop());
+        }
+
+        Ok(Some(Expr::List(commands)))
+    }
+
+    fn linebreak(&mut self) {
+        while let Some(Token::Newline) = self.peek() {
+            self.next();
+        }
+    }
+
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None
+            | Some(
+                Token::DoubleSemicolon
+                | Token::SemicolonAmpersand
+                | Token::DoubleSemicolonAmpersand
+                | Token::RightParen,
+            ) => true,
+            Some(_) => self.peek_word().is_some_and(|w| CLOSING_WORDS.contains(&w)),
+        }
+    }
+
+    /// Parses and-or lists separated by `;` or newlines, up to the end of the
+    /// input or a reserved word that closes a compound command.
+    fn list(&mut self) -> Result<Expr, ParseError> {
+        let mut commands = vec![self.and_or()?];
+
+        while let Some(Token::Semicolon | Token::Newline) = self.peek() {
+            self.next();
+            self.linebreak();
+
+            if self.at_list_end() {
+                break;
+            }
+
+            commands.push(self.and_or()?);
+        }
+
+        if commands.len() == 1 {
+            return Ok(commands.pop().unwrap());
+        }
+
+        Ok(Expr::List(commands))
+    }
 
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Ampersand => todo!(),
-                Token::Greater | Token::OneGreater | Token::TwoGreater => {
-                    self.ast = Some(self.redirect());
+    fn compound_list(&mut self) -> Result<Expr, ParseError> {
+        self.linebreak();
+        self.list()
+    }
+
+    fn and_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.pipeline()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::DoubleAmpersand) => {
+                    self.next();
+                    self.linebreak();
+                    let right = Box::new(self.pipeline()?);
+                    expr = Expr::And {
+                        left: Box::new(expr),
+                        right,
+                    };
                 }
-                Token::Literal(_) => todo!(),
-                Token::Pipe => self.ast = Some(self.pipe()),
-                Token::ZeroGreater => todo!(),
-                Token::TwoDoubleGreater | Token::OneDoubleGreater | Token::DoubleGreater => {
-                    self.ast = Some(self.append());
+                Some(Token::DoublePipe) => {
+                    self.next();
+                    self.linebreak();
+                    let right = Box::new(self.pipeline()?);
+                    expr = Expr::Or {
+                        left: Box::new(expr),
+                        right,
+                    };
                 }
-                Token::ZeroDoubleGreater => {
-                    todo!()
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Expr, ParseError> {
+        if self.peek_word() == Some("!") {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.pipeline()?)));
+        }
+
+        let mut expr = self.command()?;
+
+        while let Some(Token::Pipe) = self.peek() {
+            self.next(); // consume pipe
+            self.linebreak();
+
+            let dest = Box::new(self.command()?);
+            expr = Expr::Pipe {
+                src: Box::new(expr),
+                dest,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// Replaces the word at `index` with the tokens of its alias, unless that
+    /// alias is already being expanded. An alias whose value ends in a blank
+    /// has the word after it checked for an alias as well.
+    fn expand_alias(&mut self, index: usize, expanding: &mut Vec<String>) {
+        let Some(Token::Literal(word)) = self.tokens.get(index) else {
+            return;
+        };
+
+        let Some((name, value)) = word
+            .as_literal()
+            .filter(|name| !expanding.iter().any(|expanded| expanded == name))
+            .and_then(|name| self.aliases.get_key_value(name))
+        else {
+            return;
+        };
+
+        let tokens = Tokenizer::tokenize(value);
+        let count = tokens.len();
+        self.tokens.splice(index..=index, tokens);
+        expanding.push(name.clone());
+
+        // the following word first, since expanding the first word of the
+        // value moves it
+        if value.ends_with([' ', '\t']) {
+            self.expand_alias(index + count, expanding);
+        }
+
+        self.expand_alias(index, expanding);
+    }
+
+    fn command(&mut self) -> Result<Expr, ParseError> {
+        self.expand_alias(self.position, &mut Vec::new());
+
+        if let Some(Token::Arithmetic(expression)) = self.peek() {
+            self.next();
+            return self.redirections(Expr::Arithmetic(expression));
+        }
+
+        if let Some(Token::LeftParen) = self.peek() {
+            let expr = self.subshell()?;
+            return self.redirections(expr);
+        }
+
+        match self.peek_word() {
+            Some("{") => {
+                let expr = self.brace_group()?;
+                self.redirections(expr)
+            }
+            Some("if") => {
+                let expr = self.if_clause()?;
+                self.redirections(expr)
+            }
+            Some("while" | "until") => {
+                let expr = self.while_clause()?;
+                self.redirections(expr)
+            }
+            Some("for") => {
+                let expr = self.for_clause()?;
+                self.redirections(expr)
+            }
+            Some("case") => {
+                let expr = self.case_clause()?;
+                self.redirections(expr)
+            }
+            Some("[[") => {
+                let expr = self.conditional()?;
+                self.redirections(expr)
+            }
+            Some("function") => {
+                self.next();
+                self.function_definition()
+            }
+            Some(_) if self.is_function_definition() => self.function_definition(),
+            Some(word) if CLOSING_WORDS.contains(&word) => Err(self.unexpected()),
+            _ => self.simple_command(),
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<Expr, ParseError> {
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirections = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Literal(word)) => {
+                    self.next();
+
+                    if words.is_empty() && word.assignment_name().is_some() {
+                        assignments.push(word);
+                    } else {
+                        words.push(word);
+                    }
                 }
+                Some(token) if token.is_redirection() => redirections.push(self.redirection()?),
+                _ => break,
             }
         }
+
+        if assignments.is_empty() && words.is_empty() && redirections.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        Ok(apply_redirections(
+            Expr::Command { assignments, words },
+            redirections,
+        ))
     }
 
-    fn command(&mut self) -> Expr {
-        let Token::Literal(name) = self.next().expect("no command name found") else {
-            panic!("expected command name")
+    /// Parses the redirections following a compound command.
+    fn redirections(&mut self, expr: Expr) -> Result<Expr, ParseError> {
+        let mut redirections = Vec::new();
+
+        while self.peek().is_some_and(|token| token.is_redirection()) {
+            redirections.push(self.redirection()?);
+        }
+
+        Ok(apply_redirections(expr, redirections))
+    }
+
+    fn redirection(&mut self) -> Result<(Token, Word), ParseError> {
+        let operator = self.next().unwrap();
+
+        match self.next() {
+            Some(Token::Literal(dest)) => Ok((operator, dest)),
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedToken(Token::Newline.to_string())),
+        }
+    }
+
+    /// Parses `if list; then list; [elif list; then list;]... [else list;] fi`,
+    /// turning every `elif` into a nested `if`.
+    fn if_clause(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `if` or `elif`
+
+        let condition = Box::new(self.compound_list()?);
+        self.expect("then")?;
+        let then = Box::new(self.compound_list()?);
+
+        let otherwise = match self.peek_word() {
+            Some("elif") => Some(self.if_clause()?),
+            Some("else") => {
+                self.next();
+                let otherwise = self.compound_list()?;
+                self.expect("fi")?;
+                Some(otherwise)
+            }
+            _ => {
+                self.expect("fi")?;
+                None
+            }
+        };
+
+        Ok(Expr::If {
+            condition,
+            then,
+            otherwise: otherwise.map(Box::new),
+        })
+    }
+
+    /// Parses `while list; do list; done` and the same with `until`.
+    fn while_clause(&mut self) -> Result<Expr, ParseError> {
+        let until = self.peek_word() == Some("until");
+        self.next(); // consume `while` or `until`
+
+        let condition = Box::new(self.compound_list()?);
+        let body = Box::new(self.do_group()?);
+
+        if until {
+            Ok(Expr::Until { condition, body })
+        } else {
+            Ok(Expr::While { condition, body })
+        }
+    }
+
+    /// Parses `for name [in word...]; do list; done` and the arithmetic
+    /// `for ((init; condition; update)); do list; done`.
+    fn for_clause(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `for`
+
+        let name = match self.peek() {
+            Some(Token::Arithmetic(expression)) => {
+                self.next();
+                return self.arithmetic_for(&expression);
+            }
+            Some(Token::Literal(word)) if word.as_literal().is_some_and(is_name) => {
+                self.next();
+                word.to_string()
+            }
+            _ => return Err(self.unexpected()),
+        };
+
+        self.linebreak();
+
+        let words = if self.peek_word() == Some("in") {
+            self.next();
+            let mut words = Vec::new();
+
+            while let Some(Token::Literal(word)) = self.peek() {
+                self.next();
+                words.push(word);
+            }
+
+            match self.peek() {
+                Some(Token::Semicolon | Token::Newline) => self.next(),
+                _ => return Err(self.unexpected()),
+            };
+
+            words
+        } else {
+            if let Some(Token::Semicolon) = self.peek() {
+                self.next();
+            }
+
+            vec![positional_parameters()]
         };
 
-        let mut args = Vec::new();
+        self.linebreak();
+        let body = Box::new(self.do_group()?);
+
+        Ok(Expr::For { name, words, body })
+    }
+
+    fn arithmetic_for(&mut self, expression: &str) -> Result<Expr, ParseError> {
+        let [init, condition, update] = expression
+            .split(';')
+            .map(|part| part.trim().to_string())
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| ParseError::UnexpectedToken(format!("(({expression}))")))?;
 
-        while let Some(Token::Literal(s)) = self.peek() {
-            args.push(s);
-            self.next().unwrap();
+        if let Some(Token::Semicolon) = self.peek() {
+            self.next();
         }
 
-        Expr::Command { name, args }
+        self.linebreak();
+        let body = Box::new(self.do_group()?);
+
+        Ok(Expr::ArithmeticFor {
+            init,
+            condition,
+            update,
+            body,
+        })
     }
 
-    fn redirect(&mut self) -> Expr {
-        let src = Box::new(self.ast.take().unwrap());
+    /// Parses `case word in [(]pattern[|pattern]...) list;; ... esac`.
+    fn case_clause(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `case`
 
-        let stream = match self.next().unwrap() {
-            Token::Greater | Token::OneGreater => Stream::Stdout,
-            Token::ZeroGreater => Stream::Stdin,
-            Token::TwoGreater => Stream::Stderr,
-            t => panic!("expected redirect, found {t:?}"),
+        let Some(Token::Literal(word)) = self.peek() else {
+            return Err(self.unexpected());
         };
+        self.next();
+
+        self.linebreak();
+        self.expect("in")?;
+        self.linebreak();
+
+        let mut items = Vec::new();
+
+        while self.peek_word() != Some("esac") {
+            items.push(self.case_item()?);
+            self.linebreak();
+        }
+
+        self.next(); // consume `esac`
+
+        Ok(Expr::Case { word, items })
+    }
+
+    fn case_item(&mut self) -> Result<CaseItem, ParseError> {
+        if let Some(Token::LeftParen) = self.peek() {
+            self.next();
+        }
+
+        let mut patterns = Vec::new();
+
+        loop {
+            let Some(Token::Literal(pattern)) = self.peek() else {
+                return Err(self.unexpected());
+            };
+            self.next();
+            patterns.push(pattern);
 
-        let Some(Token::Literal(dest)) = self.next() else {
-            panic!("expected destination after redirect");
+            match self.peek() {
+                Some(Token::Pipe) => self.next(),
+                Some(Token::RightParen) => break,
+                _ => return Err(self.unexpected()),
+            };
+        }
+
+        self.next(); // consume `)`
+        self.linebreak();
+
+        let body = if self.at_list_end() {
+            None
+        } else {
+            Some(self.list()?)
+        };
+
+        let terminator = match self.peek() {
+            Some(Token::DoubleSemicolon) => CaseTerminator::Break,
+            Some(Token::SemicolonAmpersand) => CaseTerminator::FallThrough,
+            Some(Token::DoubleSemicolonAmpersand) => CaseTerminator::Continue,
+            // the last item may leave out its terminator
+            _ if self.peek_word() == Some("esac") => {
+                return Ok(CaseItem {
+                    patterns,
+                    body,
+                    terminator: CaseTerminator::Break,
+                });
+            }
+            _ => return Err(self.unexpected()),
         };
+        self.next();
 
-        Expr::Redirect { src, stream, dest }
+        Ok(CaseItem {
+            patterns,
+            body,
+            terminator,
+        })
     }
 
-    fn pipe(&mut self) -> Expr {
-        let src = Box::new(self.ast.take().unwrap());
-        self.next(); // consume pipe
+    /// Whether the next tokens are `name ( )`.
+    fn is_function_definition(&self) -> bool {
+        matches!(
+            self.tokens.get(self.position..self.position + 3),
+            Some([Token::Literal(_), Token::LeftParen, Token::RightParen])
+        )
+    }
 
-        let Expr::Command { name, args } = self.command() else {
-            panic!("expected command after pipe");
+    /// Parses `name() { list; }` or `name() ( list )` after the `function`
+    /// keyword, if any, where the parentheses are optional.
+    fn function_definition(&mut self) -> Result<Expr, ParseError> {
+        let Some(name) = self.peek_word().map(String::from) else {
+            return Err(self.unexpected());
         };
+        self.next();
+
+        if let Some(Token::LeftParen) = self.peek() {
+            self.next();
 
-        let dest = Box::new(Expr::Command { name, args });
-        Expr::Pipe { src, dest }
+            let Some(Token::RightParen) = self.peek() else {
+                return Err(self.unexpected());
+            };
+            self.next();
+        }
+
+        self.linebreak();
+
+        let body = match self.peek() {
+            Some(Token::LeftParen) => self.subshell()?,
+            _ if self.peek_word() == Some("{") => self.brace_group()?,
+            _ => return Err(self.unexpected()),
+        };
+
+        // redirections of the definition apply to every call
+        let body = self.redirections(body)?;
+
+        Ok(Expr::Function {
+            name,
+            body: Rc::new(body),
+        })
     }
 
-    pub fn ast(self) -> Expr {
-        self.ast.unwrap()
+    /// Parses `{ list; }`, which runs in the current shell.
+    fn brace_group(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `{`
+
+        let body = self.compound_list()?;
+        self.expect("}")?;
+
+        Ok(Expr::Group(Box::new(body)))
     }
 
-    fn append(&mut self) -> Expr {
-        let src = Box::new(self.ast.take().unwrap());
+    /// Parses `( list )`, which runs in a copy of the shell.
+    fn subshell(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `(`
+
+        let body = self.compound_list()?;
 
-        let stream = match self.next().unwrap() {
-            Token::DoubleGreater | Token::OneDoubleGreater => Stream::Stdout,
-            Token::ZeroDoubleGreater => Stream::Stdin,
-            Token::TwoDoubleGreater => Stream::Stderr,
-            t => panic!("expected append, found {t:?}"),
+        let Some(Token::RightParen) = self.peek() else {
+            return Err(self.unexpected());
         };
+        self.next();
+
+        Ok(Expr::Subshell(Box::new(body)))
+    }
+
+    /// Parses `[[ expression ]]`.
+    fn conditional(&mut self) -> Result<Expr, ParseError> {
+        self.next(); // consume `[[`
+
+        let condition = self.condition_or()?;
+        self.linebreak();
+        self.expect("]]")?;
+
+        Ok(Expr::Conditional(condition))
+    }
+
+    fn condition_or(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.condition_and()?;
+
+        while let Some(Token::DoublePipe) = self.peek() {
+            self.next();
+            let right = self.condition_and()?;
+            condition = Condition::Or(Box::new(condition), Box::new(right));
+        }
+
+        Ok(condition)
+    }
+
+    fn condition_and(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.condition_term()?;
+
+        while let Some(Token::DoubleAmpersand) = self.peek() {
+            self.next();
+            let right = self.condition_term()?;
+            condition = Condition::And(Box::new(condition), Box::new(right));
+        }
 
-        let Some(Token::Literal(dest)) = self.next() else {
-            panic!("expected destination after append");
+        Ok(condition)
+    }
+
+    fn condition_term(&mut self) -> Result<Condition, ParseError> {
+        self.linebreak();
+
+        if let Some(Token::LeftParen) = self.peek() {
+            self.next();
+            let condition = self.condition_or()?;
+            self.linebreak();
+
+            let Some(Token::RightParen) = self.peek() else {
+                return Err(self.unexpected());
+            };
+            self.next();
+
+            return Ok(condition);
+        }
+
+        let word = match self.peek() {
+            Some(Token::Literal(word)) if word.as_literal() != Some("]]") => word,
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+
+        if word.as_literal() == Some("!") {
+            return Ok(Condition::Not(Box::new(self.condition_term()?)));
+        }
+
+        if let Some(operator) = word.as_literal().filter(|w| is_unary_operator(w)) {
+            if let Some(Token::Literal(operand)) = self.peek() {
+                self.next();
+                return Ok(Condition::Unary {
+                    operator: operator.to_string(),
+                    operand,
+                });
+            }
+        }
+
+        let operator = match self.peek() {
+            Some(Token::Less) => "<".to_string(),
+            Some(Token::Greater) => ">".to_string(),
+            Some(Token::Literal(operator))
+                if operator.as_literal().is_some_and(is_binary_operator) =>
+            {
+                operator.to_string()
+            }
+            _ => return Ok(Condition::Word(word)),
         };
+        self.next();
 
-        Expr::Append { src, stream, dest }
+        let right = if operator == "=~" {
+            self.regex_operand()?
+        } else {
+            match self.next() {
+                Some(Token::Literal(right)) => right,
+                Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                None => return Err(ParseError::UnexpectedEof),
+            }
+        };
+
+        Ok(Condition::Binary {
+            operator,
+            left: word,
+            right,
+        })
+    }
+
+    /// Parses the right side of `=~`, where parentheses and `|` belong to the
+    /// regular expression instead of separating words.
+    fn regex_operand(&mut self) -> Result<Word, ParseError> {
+        let mut regex = Word::default();
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                Some(Token::Literal(word)) if word.as_literal() == Some("]]") && depth == 0 => {
+                    break;
+                }
+                Some(Token::Literal(word)) => {
+                    for segment in word.segments() {
+                        regex.push_segment(segment.clone());
+                    }
+                }
+                Some(Token::LeftParen) => {
+                    depth += 1;
+                    regex.push_literal("(");
+                }
+                Some(Token::RightParen) if depth > 0 => {
+                    depth -= 1;
+                    regex.push_literal(")");
+                }
+                Some(Token::Pipe) => regex.push_literal("|"),
+                Some(Token::Less) => regex.push_literal("<"),
+                Some(Token::Greater) => regex.push_literal(">"),
+                _ => break,
+            }
+            self.next();
+        }
+
+        if regex.segments().is_empty() {
+            return Err(self.unexpected());
+        }
+
+        Ok(regex)
     }
+
+    fn do_group(&mut self) -> Result<Expr, ParseError> {
+        self.expect("do")?;
+        let body = self.compound_list()?;
+        self.expect("done")?;
+        Ok(body)
+    }
+
+    /// Tokenizes and parses `source` with `aliases` expanded, returning `None`
+    /// if it holds no command.
+    pub fn parse_source(
+        source: &str,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Option<Expr>, ParseError> {
+        let tokens = Tokenizer::tokenize_complete(source).ok_or(ParseError::UnexpectedEof)?;
+        Parser::new(tokens, aliases).parse()
+    }
+}
+
+/// Whether `word` is a unary operator of `test` and `[[ ... ]]`.
+pub fn is_unary_operator(word: &str) -> bool {
+    matches!(
+        word,
+        "-e" | "-f" | "-d" | "-x" | "-r" | "-w" | "-s" | "-L" | "-h" | "-z" | "-n"
+    )
+}
+
+/// Whether `word` is a binary operator of `test` and `[[ ... ]]`.
+pub fn is_binary_operator(word: &str) -> bool {
+    matches!(
+        word,
+        "=" | "=="
+            | "!="
+            | "<"
+            | ">"
+            | "=~"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+/// The `"$@"` word a `for` loop without `in` iterates over.
+fn positional_parameters() -> Word {
+    let mut parameters = Word::default();
+    parameters.push_segment(Segment::Parameter("@".to_string()));
+
+    let mut word = Word::default();
+    word.push_segment(Segment::DoubleQuoted(parameters));
+    word
+}
+
+/// Wraps `expr` in its redirections, the first one outermost so that the last
+/// redirection of a stream is the one that takes effect.
+fn apply_redirections(expr: Expr, redirections: Vec<(Token, Word)>) -> Expr {
+    redirections
+        .into_iter()
+        .rev()
+        .fold(expr, |src, (operator, dest)| {
+            let src = Box::new(src);
+
+            match operator {
+                Token::Greater | Token::OneGreater => Expr::Redirect {
+                    src,
+                    stream: Stream::Stdout,
+                    dest,
+                },
+                Token::Less | Token::ZeroGreater => Expr::Redirect {
+                    src,
+                    stream: Stream::Stdin,
+                    dest,
+                },
+                Token::TwoGreater => Expr::Redirect {
+                    src,
+                    stream: Stream::Stderr,
+                    dest,
+                },
+                Token::DoubleGreater | Token::OneDoubleGreater => Expr::Append {
+                    src,
+                    stream: Stream::Stdout,
+                    dest,
+                },
+                Token::ZeroDoubleGreater => Expr::Append {
+                    src,
+                    stream: Stream::Stdin,
+                    dest,
+                },
+                Token::TwoDoubleGreater => Expr::Append {
+                    src,
+                    stream: Stream::Stderr,
+                    dest,
+                },
+                t => panic!("expected redirect, found {t:?}"),
+            }
+        })
 }
@@ -0,0 +1,224 @@
+use std::ffi::{CStr, c_char};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::{expand::expand_to_string, shell::Shell, tokenizer::Tokenizer, users};
+
+/// Marks the start and the end of characters that take no room on the
+/// screen, written as `\[` and `\]` in a prompt.
+const NON_PRINTING_START: char = '\x01';
+const NON_PRINTING_END: char = '\x02';
+
+/// A prompt ready to be written in raw mode.
+#[derive(Default)]
+pub struct Prompt {
+    pub text: String,
+    /// The number of columns taken by the last line of the prompt, which is
+    /// where the command line starts.
+    pub width: usize,
+}
+
+/// Expands the backslash escapes of a prompt string, then its parameters,
+/// command substitutions and arithmetic expansions, leaving quotes as they
+/// are.
+pub fn expand(shell: &mut Shell, template: &str) -> Prompt {
+    let decoded = decode_escapes(shell, template);
+
+    let expanded =
+        expand_to_string(shell, &Tokenizer::double_quoted(&decoded)).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            decoded
+        });
+
+    let mut text = String::new();
+    let mut width = 0;
+    let mut chars = expanded.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            NON_PRINTING_START => {
+                for c in chars.by_ref() {
+                    if c == NON_PRINTING_END {
+                        break;
+                    }
+                    text.push(c);
+                }
+            }
+            NON_PRINTING_END => {}
+            // escape sequences take no room even without `\[ \]`
+            '\x1b' => {
+                text.push(c);
+                if chars.next_if_eq(&'[').is_some() {
+                    text.push('[');
+                    for c in chars.by_ref() {
+                        text.push(c);
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\n' => {
+                text.push_str("\r\n");
+                width = 0;
+            }
+            c => {
+                text.push(c);
+                width += c.width().unwrap_or(0);
+            }
+        }
+    }
+
+    Prompt { text, width }
+}
+
+fn decode_escapes(shell: &mut Shell, template: &str) -> String {
+    let mut decoded = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+
+        let Some(escape) = chars.next() else {
+            decoded.push('\\');
+            break;
+        };
+
+        match escape {
+            'u' => push_verbatim(&mut decoded, &user_name(shell)),
+            'h' => push_verbatim(
+                &mut decoded,
+                host_name().split('.').next().unwrap_or_default(),
+            ),
+            'H' => push_verbatim(&mut decoded, &host_name()),
+            'w' => push_verbatim(&mut decoded, &working_directory(shell)),
+            'W' => {
+                let directory = working_directory(shell);
+                let base = match directory.rsplit_once('/') {
+                    Some((_, base)) if !base.is_empty() => base,
+                    _ => &directory,
+                };
+                push_verbatim(&mut decoded, base);
+            }
+            // SAFETY: geteuid cannot fail
+            '$' if unsafe { libc::geteuid() } == 0 => decoded.push('#'),
+            '$' => decoded.push_str("\\$"),
+            't' => decoded.push_str(&local_time(c"%H:%M:%S")),
+            'T' => decoded.push_str(&local_time(c"%I:%M:%S")),
+            '@' => decoded.push_str(&local_time(c"%I:%M %p")),
+            'A' => decoded.push_str(&local_time(c"%H:%M")),
+            'd' => decoded.push_str(&local_time(c"%a %b %d")),
+            'g' => {
+                if let Some(status) = shell.git_status() {
+                    push_verbatim(&mut decoded, &status.branch);
+                }
+            }
+            'G' => {
+                if let Some(status) = shell.git_status() {
+                    push_verbatim(&mut decoded, &status.segment());
+                }
+            }
+            // there is no job control, so no jobs
+            'j' => decoded.push('0'),
+            '?' => decoded.push_str(&shell.status().to_string()),
+            's' => push_verbatim(
+                &mut decoded,
+                shell.arg0().rsplit('/').next().unwrap_or_default(),
+            ),
+            'n' => decoded.push('\n'),
+            'a' => decoded.push('\x07'),
+            'e' => decoded.push('\x1b'),
+            '[' => decoded.push(NON_PRINTING_START),
+            ']' => decoded.push(NON_PRINTING_END),
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else {
+                        break;
+                    };
+                    chars.next();
+                    code = code * 8 + digit;
+                }
+                decoded.extend(char::from_u32(code));
+            }
+            // keep the backslash of `\\` and `\$` for quote removal
+            escape => {
+                decoded.push('\\');
+                decoded.push(escape);
+            }
+        }
+    }
+
+    decoded
+}
+
+/// Appends text that must come out of expansion unchanged.
+fn push_verbatim(decoded: &mut String, text: &str) {
+    for c in text.chars() {
+        if "\\$`\"'~".contains(c) {
+            decoded.push('\\');
+        }
+        decoded.push(c);
+    }
+}
+
+fn user_name(shell: &Shell) -> String {
+    shell
+        .variables()
+        .get("USER")
+        .map(String::from)
+        .or_else(users::current_user_name)
+        .unwrap_or_default()
+}
+
+fn host_name() -> String {
+    let mut buffer = [0 as c_char; 256];
+
+    // SAFETY: the buffer is as long as the length handed to gethostname
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return String::new();
+    }
+
+    // SAFETY: gethostname wrote a NUL-terminated name
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The current directory, with the home directory abbreviated to `~`.
+fn working_directory(shell: &Shell) -> String {
+    let pwd = shell.variables().get("PWD").unwrap_or_default();
+
+    match shell.variables().get("HOME") {
+        Some(home) if !home.is_empty() && home != "/" => match pwd.strip_prefix(home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{rest}"),
+            _ => pwd.to_string(),
+        },
+        _ => pwd.to_string(),
+    }
+}
+
+fn local_time(format: &CStr) -> String {
+    let mut buffer = [0 as c_char; 64];
+
+    // SAFETY: `tm` is filled in by localtime_r before strftime reads it, and
+    // strftime writes at most `buffer.len()` bytes
+    let length = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed();
+        libc::localtime_r(&raw const now, &raw mut tm);
+        libc::strftime(
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            format.as_ptr(),
+            &raw const tm,
+        )
+    };
+
+    // SAFETY: strftime wrote `length` bytes
+    let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+    String::from_utf8_lossy(bytes).into_owned()
+}
@@ -1,18 +1,61 @@
-use std::fmt::Display;
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::fs;
 use std::io::{self, Stdout, Write, stdout};
 use std::ops::ControlFlow;
-use std::path::PathBuf;
-use std::process::Stdio;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use termion::{
     clear, cursor,
-    event::Key,
+    event::{Event, Key},
     input::TermRead,
     raw::{IntoRawMode, RawTerminal},
+    style, terminal_size,
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 use crate::commands::Executable;
+use crate::git;
+use crate::highlight::highlight;
 use crate::history::History;
-use crate::{commands::get_commands, parser::Parser, tokenizer::Tokenizer};
+use crate::keymap::{Action, BellStyle, Binding, Keymap, Lookup};
+use crate::options::Options;
+use crate::parser::{Expr, ParseError};
+use crate::prompt::{self, Prompt};
+use crate::stdio::{Input, Output};
+use crate::users;
+use crate::variables::Variables;
+use crate::{
+    commands::{get_commands, run_editor},
+    eval::error_message,
+    parser::Parser,
+    tokenizer::Tokenizer,
+};
+
+/// A `break` or `continue` on its way out to the loop it applies to,
+/// counting the loops still to be left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Break(usize),
+    Continue(usize),
+    Return,
+}
+
+/// Make the terminal mark pasted text with `PASTE_START` and `PASTE_END`,
+/// so that it is inserted rather than typed.
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Set by the `SIGWINCH` handler when the terminal has been resized.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
 
 #[derive(Debug, PartialEq)]
 enum CompletionState {
@@ -22,96 +65,464 @@ enum CompletionState {
 
 pub struct Shell {
     buffer: String,
-    stdout: RawTerminal<Stdout>,
+    /// Where in the buffer the cursor is, in bytes, always at the start of a
+    /// grapheme.
+    cursor: usize,
+    /// The lines of a command that continues on the line being edited.
+    pending: String,
+    stdout: Option<RawTerminal<Stdout>>,
     raw_mode: bool,
+    /// The last line of the prompt, which the command line follows.
+    prompt: Prompt,
+    rprompt: Option<Prompt>,
+    columns: usize,
     completion_state: CompletionState,
     history: History,
+    keymap: Keymap,
+    /// The keys typed so far of a sequence bound as a whole.
+    keys: Vec<Key>,
+    /// The text pasted so far, while a paste is being read.
+    paste: Option<String>,
+    /// The rest of the history entry suggested for the buffer, shown dimmed
+    /// after the cursor.
+    suggestion: Option<String>,
+    options: Options,
+    variables: Variables,
+    status: i32,
+    flow: Option<Flow>,
+    loop_depth: usize,
+    functions: HashMap<String, Rc<Expr>>,
+    aliases: HashMap<String, String>,
+    positional: Vec<String>,
+    call_depth: usize,
+    git: git::Cache,
+    pid: u32,
+    arg0: String,
 }
 
 impl Shell {
     pub fn new() -> Self {
         Self {
             buffer: String::new(),
-            stdout: stdout().into_raw_mode().expect("failed to set raw mode"),
-            raw_mode: true,
+            cursor: 0,
+            pending: String::new(),
+            stdout: None,
+            raw_mode: false,
+            prompt: Prompt::default(),
+            rprompt: None,
+            columns: 80,
             completion_state: CompletionState::None,
             history: History::new(),
+            keymap: Keymap::default(),
+            keys: Vec::new(),
+            paste: None,
+            suggestion: None,
+            options: Options::default(),
+            variables: Self::initial_variables(),
+            status: 0,
+            flow: None,
+            loop_depth: 0,
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            positional: Vec::new(),
+            call_depth: 0,
+            git: git::Cache::default(),
+            pid: std::process::id(),
+            arg0: std::env::args().next().unwrap_or_default(),
         }
     }
 
     pub fn run(&mut self) {
+        self.read_inputrc();
+        self.stdout = Some(stdout().into_raw_mode().expect("failed to set raw mode"));
+        self.raw_mode = true;
         self.read_history_file();
-        self.set_raw_mode(true);
+
+        // without SA_RESTART, a resize interrupts the read of the next key
+        // SAFETY: the handler only stores to an atomic
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_sigwinch as *const () as libc::sighandler_t;
+            libc::sigaction(libc::SIGWINCH, &raw const action, std::ptr::null_mut());
+        }
 
         loop {
-            self.display(format!("$ {}", self.buffer));
+            self.show_prompt();
 
-            for key in io::stdin().keys().flatten() {
-                if let ControlFlow::Break(()) = self.handle_key(key) {
-                    break;
+            let mut events = io::stdin().events();
+            loop {
+                match events.next() {
+                    Some(Ok(event)) => {
+                        if let ControlFlow::Break(()) = self.handle_event(event) {
+                            break;
+                        }
+                    }
+                    Some(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {
+                        if RESIZED.swap(false, Ordering::Relaxed) {
+                            self.handle_resize();
+                        }
+                    }
+                    Some(Err(_)) => {}
+                    None => break,
                 }
             }
         }
     }
 
+    /// Reads the size of the terminal, exporting it as `$COLUMNS` and
+    /// `$LINES` for the commands the shell runs.
+    fn update_terminal_size(&mut self) {
+        // a terminal that does not know its size reports zero columns
+        let Ok((columns @ 1.., lines)) = terminal_size() else {
+            return;
+        };
+
+        self.columns = usize::from(columns);
+        self.variables.set("COLUMNS", columns.to_string());
+        self.variables.export("COLUMNS");
+        self.variables.set("LINES", lines.to_string());
+        self.variables.export("LINES");
+    }
+
+    /// Redraws the last line of the prompt and the buffer for the new width
+    /// of the terminal, which may have moved the wrapped lines around.
+    fn handle_resize(&mut self) {
+        // the line was drawn at the old width, so that is where the cursor is
+        let (row, _) = self.cursor_position();
+        self.update_terminal_size();
+
+        let mut output = String::new();
+        if row > 0 {
+            write!(output, "{}", cursor::Up(u16::try_from(row).unwrap())).unwrap();
+        }
+        write!(output, "\r{}{}", clear::AfterCursor, self.prompt_line()).unwrap();
+        output.push_str(&self.rendered_buffer());
+
+        self.display(output);
+    }
+
+    /// Writes `$PS1`, or `$PS2` while a command continues on the next line,
+    /// followed by the buffer. Before the primary prompt `$PROMPT_COMMAND` is
+    /// run, and `$RPROMPT` is written at the right edge of the terminal.
+    fn show_prompt(&mut self) {
+        let primary = self.pending.is_empty();
+
+        // expanding a prompt may run commands, which expect a cooked terminal
+        self.set_raw_mode(false);
+
+        if let Some(command) = self.variables.get("PROMPT_COMMAND").filter(|_| primary) {
+            let command = command.to_string();
+            let status = self.status;
+            self.run_script(&command);
+            self.status = status;
+        }
+
+        let (name, default) = if primary {
+            ("PS1", "$ ")
+        } else {
+            ("PS2", "> ")
+        };
+        let template = self.variables.get(name).unwrap_or(default).to_string();
+        let mut prompt = prompt::expand(self, &template);
+
+        self.rprompt = match self.variables.get("RPROMPT").filter(|_| primary) {
+            Some(template) => {
+                let template = template.to_string();
+                Some(prompt::expand(self, &template))
+            }
+            None => None,
+        };
+        self.update_terminal_size();
+
+        // only the last line is redrawn when the terminal is resized
+        let mut output = String::new();
+        if let Some((lines, last)) = prompt.text.rsplit_once("\r\n") {
+            output = format!("{lines}\r\n");
+            prompt.text = last.to_string();
+        }
+        self.prompt = prompt;
+
+        output.push_str(BRACKETED_PASTE_ON);
+        output.push_str(&self.prompt_line());
+        output.push_str(&self.rendered_buffer());
+        self.display(output);
+    }
+
+    /// The last line of the prompt, with `$RPROMPT` at the right edge of the
+    /// terminal if both fit.
+    fn prompt_line(&self) -> String {
+        let mut line = self.prompt.text.clone();
+
+        if let Some(rprompt) = &self.rprompt {
+            if self.prompt.width + rprompt.width < self.columns {
+                let offset = u16::try_from(self.columns - rprompt.width).unwrap();
+                write!(
+                    line,
+                    "{}\r{}{}{}",
+                    cursor::Save,
+                    cursor::Right(offset),
+                    rprompt.text,
+                    cursor::Restore,
+                )
+                .unwrap();
+            }
+        }
+
+        line
+    }
+
     fn display(&mut self, s: impl Display) {
         self.set_raw_mode(true);
-        write!(self.stdout, "{s}").expect("failed to write to raw stdout");
-        self.stdout.flush().expect("failed to flush stdout");
+        let stdout = self.stdout.as_mut().expect("not attached to a terminal");
+        write!(stdout, "{s}").expect("failed to write to raw stdout");
+        stdout.flush().expect("failed to flush stdout");
     }
 
     fn bell(&mut self) {
-        self.display("\x07");
+        if self.keymap.bell_style == BellStyle::Audible {
+            self.display("\x07");
+        }
     }
 
     fn set_raw_mode(&mut self, raw_mode: bool) {
+        let Some(stdout) = &self.stdout else {
+            return;
+        };
+
         if self.raw_mode == raw_mode {
             return;
         }
         self.raw_mode = raw_mode;
 
         if raw_mode {
-            self.stdout
+            stdout
                 .activate_raw_mode()
                 .expect("failed to activated raw mode");
         } else {
-            self.stdout
+            stdout
                 .suspend_raw_mode()
                 .expect("failed to suspend raw mode");
         }
     }
 
+    fn handle_event(&mut self, event: Event) -> ControlFlow<()> {
+        match event {
+            Event::Unsupported(sequence) if sequence == PASTE_START => {
+                self.paste = Some(String::new());
+            }
+            Event::Unsupported(sequence) if sequence == PASTE_END => {
+                if let Some(text) = self.paste.take() {
+                    let mut buffer = self.buffer.clone();
+                    buffer.insert_str(self.cursor, &text);
+                    self.edit(buffer, self.cursor + text.len());
+                }
+            }
+            // pasted text is kept as it is, without running or completing it
+            Event::Key(key) => match &mut self.paste {
+                Some(text) => {
+                    if let Key::Char(c) = key {
+                        text.push(c);
+                    }
+                }
+                None => return self.handle_key(key),
+            },
+            _ => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
     fn handle_key(&mut self, key: Key) -> ControlFlow<()> {
-        match key {
-            Key::Char('\t') => self.handle_autocompletion(),
-            Key::Char('\n') => {
-                self.handle_enter();
-                ControlFlow::Break(())
+        if key == Key::Ctrl('c') {
+            self.display(BRACKETED_PASTE_OFF);
+            std::process::exit(0)
+        }
+
+        self.keys.push(key);
+
+        match self.keymap.lookup(&self.keys) {
+            Lookup::Prefix => ControlFlow::Continue(()),
+            Lookup::Bound(binding) => {
+                let binding = binding.clone();
+                self.keys.clear();
+                self.run_binding(binding)
             }
-            Key::Char(c) => {
-                self.display(c);
-                self.buffer.push(c);
+            Lookup::Unbound => {
+                let mut keys = std::mem::take(&mut self.keys);
+                let rest = keys.split_off(1);
+
+                // keys that start no binding after all are taken one at a
+                // time: the first on its own, then the others read again
+                match (self.keymap.binding(&keys).cloned(), keys[0]) {
+                    (Some(binding), _) => self.run_binding(binding)?,
+                    (None, Key::Char(c)) => self.insert(c.encode_utf8(&mut [0; 4])),
+                    (None, _) => self.bell(),
+                }
+
+                for key in rest {
+                    self.handle_key(key)?;
+                }
                 ControlFlow::Continue(())
             }
-            Key::Backspace if !self.buffer.is_empty() => {
-                self.buffer.pop();
-                self.display(format!("{}{}", cursor::Left(1), clear::AfterCursor));
+        }
+    }
+
+    fn run_binding(&mut self, binding: Binding) -> ControlFlow<()> {
+        match binding {
+            Binding::Action(action) => self.run_action(action),
+            Binding::Macro(text) => {
+                self.insert(&text);
                 ControlFlow::Continue(())
             }
-            Key::Ctrl('c') => std::process::exit(0),
-            Key::Up => {
-                self.handle_up_arrow();
-                ControlFlow::Continue(())
+        }
+    }
+
+    fn run_action(&mut self, action: Action) -> ControlFlow<()> {
+        let at_end = self.cursor == self.buffer.len();
+
+        match action {
+            Action::AcceptLine => {
+                self.handle_enter();
+                return ControlFlow::Break(());
             }
-            Key::Down => {
-                self.handle_down_arrow();
-                ControlFlow::Continue(())
+            Action::Complete => return self.handle_autocompletion(),
+            Action::BackwardDeleteChar if self.cursor > 0 => {
+                let start = self.previous_grapheme();
+                let mut buffer = self.buffer.clone();
+                buffer.replace_range(start..self.cursor, "");
+                self.edit(buffer, start);
+            }
+            Action::DeleteChar if !at_end => {
+                let end = self.next_grapheme();
+                let mut buffer = self.buffer.clone();
+                buffer.replace_range(self.cursor..end, "");
+                self.edit(buffer, self.cursor);
+            }
+            Action::BackwardChar => self.edit(self.buffer.clone(), self.previous_grapheme()),
+            Action::BackwardWord => self.edit(self.buffer.clone(), self.previous_word()),
+            Action::BeginningOfLine => self.edit(self.buffer.clone(), 0),
+            Action::ForwardChar if !at_end => {
+                self.edit(self.buffer.clone(), self.next_grapheme());
+            }
+            Action::ForwardWord if !at_end => self.edit(self.buffer.clone(), self.next_word()),
+            Action::EndOfLine if !at_end => self.edit(self.buffer.clone(), self.buffer.len()),
+            // moving past the end of the line accepts the suggestion
+            Action::ForwardChar | Action::EndOfLine => {
+                if let Some(suggestion) = self.suggestion.clone() {
+                    self.set_buffer(self.buffer.clone() + &suggestion);
+                }
             }
-            k => {
+            Action::ForwardWord => self.accept_suggested_word(),
+            Action::PreviousHistory => self.handle_up_arrow(),
+            Action::NextHistory => self.handle_down_arrow(),
+            Action::EditAndExecuteCommand => return self.edit_buffer_in_editor(),
+            Action::ReReadInitFile => {
+                self.newline();
                 self.set_raw_mode(false);
-                todo!("{:?}", k);
+                self.read_inputrc();
+                return ControlFlow::Break(());
+            }
+            Action::BackwardDeleteChar | Action::DeleteChar => {}
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Inserts text at the cursor.
+    fn insert(&mut self, text: &str) {
+        let mut buffer = self.buffer.clone();
+        buffer.insert_str(self.cursor, text);
+        self.edit(buffer, self.cursor + text.len());
+    }
+
+    /// Where the word before the cursor starts.
+    fn previous_word(&self) -> usize {
+        let before = self.buffer[..self.cursor].trim_end_matches(|c: char| !c.is_alphanumeric());
+        before
+            .rfind(|c: char| !c.is_alphanumeric())
+            .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8())
+    }
+
+    /// Where the word after the cursor ends.
+    fn next_word(&self) -> usize {
+        let after = &self.buffer[self.cursor..];
+        let start = after.len()
+            - after
+                .trim_start_matches(|c: char| !c.is_alphanumeric())
+                .len();
+        self.cursor
+            + after[start..]
+                .find(|c: char| !c.is_alphanumeric())
+                .map_or(after.len(), |end| start + end)
+    }
+
+    /// Reads `$INPUTRC`, or `~/.inputrc`, over the current key bindings, so
+    /// that reading it again keeps those made with `bind` since.
+    pub fn read_inputrc(&mut self) {
+        let path = if let Some(path) = self.variables.get("INPUTRC") {
+            PathBuf::from(path)
+        } else {
+            let Some(home) = self
+                .variables
+                .get("HOME")
+                .map(String::from)
+                .or_else(users::current_home_dir)
+            else {
+                return;
+            };
+            PathBuf::from(home).join(".inputrc")
+        };
+
+        match self.keymap.read_file(&path) {
+            Ok(errors) => {
+                for e in errors {
+                    eprintln!("{e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("{}: {}", path.display(), error_message(&e)),
+        }
+    }
+
+    /// Opens the buffer in `$VISUAL` or `$EDITOR`, then runs what was saved
+    /// as if it had been typed.
+    fn edit_buffer_in_editor(&mut self) -> ControlFlow<()> {
+        let editor = self
+            .variables
+            .get("VISUAL")
+            .or_else(|| self.variables.get("EDITOR"))
+            .unwrap_or("vi")
+            .to_string();
+        let text = self.buffer.clone() + "\n";
+
+        self.newline();
+        self.set_raw_mode(false);
+
+        // the editor's exit status is not the status of a command line
+        let status = self.status;
+        let edited = run_editor(self, &editor, &text);
+        self.status = status;
+
+        self.buffer.clear();
+        self.cursor = 0;
+
+        let edited = match edited {
+            Ok(Some(edited)) => edited.trim_end_matches('\n').to_string(),
+            Ok(None) => return ControlFlow::Break(()),
+            Err(e) => {
+                eprintln!("{editor}: {}", error_message(&e));
+                return ControlFlow::Break(());
             }
+        };
+
+        if edited.is_empty() {
+            return ControlFlow::Break(());
         }
+
+        self.display(self.prompt_line());
+        self.set_buffer(edited);
+        self.handle_enter();
+        ControlFlow::Break(())
     }
 
     fn handle_up_arrow(&mut self) {
@@ -119,18 +530,7 @@ impl Shell {
             return;
         };
 
-        if self.buffer.is_empty() {
-            self.display(&command);
-        } else {
-            self.display(format!(
-                "{}{}{}",
-                cursor::Left(self.buffer.len().try_into().unwrap()),
-                clear::AfterCursor,
-                command,
-            ));
-        }
-
-        self.buffer = command;
+        self.set_buffer(command);
     }
 
     fn handle_down_arrow(&mut self) {
@@ -138,25 +538,156 @@ impl Shell {
             return;
         };
 
-        if self.buffer.is_empty() {
-            self.display(&command);
-        } else {
-            self.display(format!(
-                "{}{}{}",
-                cursor::Left(self.buffer.len().try_into().unwrap()),
-                clear::AfterCursor,
-                command,
-            ));
+        self.set_buffer(command);
+    }
+
+    /// Replaces the command line, redrawing the whole of it so that its
+    /// highlighting follows the edit.
+    fn set_buffer(&mut self, buffer: String) {
+        let cursor = buffer.len();
+        self.edit(buffer, cursor);
+    }
+
+    /// Replaces the command line and moves the cursor to `cursor`.
+    fn edit(&mut self, buffer: String, cursor: usize) {
+        let mut output = move_cursor(self.cursor_position(), self.position_after(""));
+
+        self.buffer = buffer;
+        self.cursor = cursor;
+        output.push_str(clear::AfterCursor.as_ref());
+        output.push_str(&self.rendered_buffer());
+        self.display(output);
+    }
+
+    /// The highlighted buffer followed by the suggestion for it, leaving the
+    /// cursor at the end of the buffer.
+    fn rendered_buffer(&mut self) -> String {
+        // in raw mode a line feed alone does not return to the first column
+        let mut output = self.highlighted_buffer().replace('\n', "\r\n");
+
+        // a suggestion is only shown when typing at the end of the line
+        let directory = self.variables.get("PWD").unwrap_or_default();
+        self.suggestion = self
+            .history
+            .suggest(&self.buffer, directory)
+            .filter(|_| self.cursor == self.buffer.len())
+            .map(|command| command[self.buffer.len()..].to_string());
+
+        let mut text = self.buffer.clone();
+        if let Some(suggestion) = &self.suggestion {
+            let shown = suggestion.replace('\n', "\r\n");
+            write!(output, "{}{shown}{}", style::Faint, style::Reset).unwrap();
+            text.push_str(suggestion);
+        }
+
+        // a line filled to the last column leaves the cursor there until
+        // something is written, so write a blank to move to the next row
+        let end = self.position_after(&text);
+        if end.0 > 0 && end.1 == 0 && !text.ends_with('\n') {
+            output.push_str(" \r");
         }
 
-        self.buffer = command;
+        output.push_str(&move_cursor(end, self.cursor_position()));
+        output
+    }
+
+    /// Where the cursor is, as a row and a column counted from the start of
+    /// the last line of the prompt.
+    fn cursor_position(&self) -> (usize, usize) {
+        self.position_after(&self.buffer[..self.cursor])
+    }
+
+    /// Where the grapheme before the cursor starts.
+    fn previous_grapheme(&self) -> usize {
+        self.buffer[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(start, _)| start)
+    }
+
+    /// Where the grapheme after the cursor ends.
+    fn next_grapheme(&self) -> usize {
+        self.buffer[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    /// Where writing `text` after the prompt leaves the cursor, wrapping at
+    /// the width of the terminal. Wide characters take two columns and move
+    /// to the next row rather than being split.
+    fn position_after(&self, text: &str) -> (usize, usize) {
+        let mut row = self.prompt.width / self.columns;
+        let mut column = self.prompt.width % self.columns;
+
+        for grapheme in text.graphemes(true) {
+            match grapheme {
+                "\n" => {
+                    row += 1;
+                    column = 0;
+                    continue;
+                }
+                // a tab stops at the last column rather than wrapping
+                "\t" => column = ((column / 8 + 1) * 8).min(self.columns - 1),
+                grapheme => {
+                    let width = grapheme.width();
+                    if column + width > self.columns {
+                        row += 1;
+                        column = 0;
+                    }
+                    column += width;
+                }
+            }
+
+            if column == self.columns {
+                row += 1;
+                column = 0;
+            }
+        }
+
+        (row, column)
+    }
+
+    /// Accepts the suggestion up to the end of its next word.
+    fn accept_suggested_word(&mut self) {
+        let Some(suggestion) = &self.suggestion else {
+            return;
+        };
+
+        let start = suggestion.len() - suggestion.trim_start().len();
+        let end = suggestion[start..]
+            .find(char::is_whitespace)
+            .map_or(suggestion.len(), |end| start + end);
+
+        let buffer = self.buffer.clone() + &suggestion[..end];
+        self.set_buffer(buffer);
+    }
+
+    /// The command line as it is shown, colored unless `$NO_COLOR` is set.
+    fn highlighted_buffer(&self) -> String {
+        if self
+            .variables
+            .get("NO_COLOR")
+            .is_some_and(|value| !value.is_empty())
+        {
+            return self.buffer.clone();
+        }
+
+        highlight(self, &self.buffer)
     }
 
     fn handle_autocompletion(&mut self) -> ControlFlow<()> {
-        let commands = get_commands();
+        let commands = get_commands(self);
         let mut completions: Vec<String> = commands
             .into_iter()
-            .filter(|s| s.starts_with(&self.buffer) && s != &self.buffer)
+            .filter(|s| {
+                let prefix = if self.keymap.completion_ignore_case {
+                    s.to_lowercase().starts_with(&self.buffer.to_lowercase())
+                } else {
+                    s.starts_with(&self.buffer)
+                };
+                prefix && s != &self.buffer
+            })
             .collect();
 
         completions.sort();
@@ -176,14 +707,7 @@ impl Shell {
     }
 
     fn prefix_completion(&mut self, prefix: &str) {
-        self.display(format!(
-            "{}{}{}",
-            cursor::Left(self.buffer.len().try_into().unwrap()),
-            clear::AfterCursor,
-            prefix,
-        ));
-
-        self.buffer = String::from(prefix);
+        self.set_buffer(String::from(prefix));
     }
 
     fn multiple_completions(&mut self, completions: &[String]) -> ControlFlow<()> {
@@ -208,48 +732,250 @@ impl Shell {
         ControlFlow::Break(())
     }
 
+    /// Moves to the next line, clearing the suggestion after the cursor, to
+    /// leave the line editor.
     fn newline(&mut self) {
+        let end = move_cursor(self.cursor_position(), self.position_after(&self.buffer));
+        self.suggestion = None;
         self.display(format!(
-            "\n{}",
-            cursor::Left(u16::try_from(self.buffer.len()).unwrap() + 2)
+            "{end}{}{BRACKETED_PASTE_OFF}\r\n",
+            clear::AfterCursor
         ));
     }
 
     fn handle_enter(&mut self) {
-        self.history.add(self.buffer.clone());
-
         self.newline();
 
-        let tokens = Tokenizer::tokenize(&self.buffer);
+        self.pending.push_str(&self.buffer);
+        self.buffer.clear();
+        self.cursor = 0;
 
-        let mut parser = Parser::new(tokens);
-        parser.parse();
-        let ast = parser.ast();
+        let parsed = Parser::parse_source(&self.pending, &self.aliases);
+        if let Err(ParseError::UnexpectedEof) = parsed {
+            // keep reading lines until the command is complete
+            self.pending.push('\n');
+            return;
+        }
 
-        self.set_raw_mode(false);
-        ast.execute(self, Stdio::inherit(), io::stdout(), io::stderr())
-            .map(|mut child| child.wait());
+        // a command continued over several lines is a single entry
+        let directory = self.variables.get("PWD").unwrap_or_default().to_string();
+        self.history
+            .add_in_directory(self.pending.clone(), directory);
 
-        self.buffer.clear();
+        match parsed {
+            Err(e) => {
+                self.set_raw_mode(false);
+                eprintln!("{e}");
+                self.status = 2;
+            }
+            Ok(Some(ast)) => {
+                self.set_raw_mode(false);
+                self.status = ast
+                    .execute(self, Input::Inherit, Output::Stdout, Output::Stderr)
+                    .wait();
+            }
+            Ok(None) => {}
+        }
+
+        self.pending.clear();
+    }
+
+    /// Runs the commands of a script and returns the exit status of the last
+    /// one.
+    pub fn run_script(&mut self, source: &str) -> i32 {
+        if let Err(e) = self.run_source(source, &Input::Inherit, &Output::Stdout, &Output::Stderr) {
+            eprintln!("{e}");
+            self.status = 2;
+        }
+
+        self.status
+    }
+
+    /// Parses and runs `source` one complete command at a time, stopping at
+    /// a syntax error or at a `return`, `break` or `continue` that leaves it.
+    pub fn run_source(
+        &mut self,
+        source: &str,
+        stdin: &Input,
+        stdout: &Output,
+        stderr: &Output,
+    ) -> Result<i32, ParseError> {
+        let tokens = Tokenizer::tokenize_complete(source).ok_or(ParseError::UnexpectedEof)?;
+        let (mut tokens, mut position) = (tokens, 0);
+
+        loop {
+            let mut parser = Parser::resume(tokens, position, &self.aliases);
+            let command = parser.complete_command();
+            (tokens, position) = parser.into_tokens();
+
+            let Some(command) = command? else {
+                return Ok(self.status);
+            };
+
+            self.status = command
+                .execute(
+                    self,
+                    stdin.try_clone().unwrap(),
+                    stdout.try_clone().unwrap(),
+                    stderr.try_clone().unwrap(),
+                )
+                .wait();
+
+            if self.flow.is_some() {
+                return Ok(self.status);
+            }
+        }
+    }
+
+    /// Runs a startup file if it exists.
+    pub fn source_file(&mut self, path: &Path) {
+        if let Ok(source) = fs::read_to_string(path) {
+            self.run_script(&source);
+        }
     }
 
     fn single_completion(&mut self, completion: String) {
-        self.display(format!(
-            "{}{}{} ",
-            cursor::Left(self.buffer.len().try_into().unwrap()),
-            clear::AfterCursor,
-            completion,
-        ));
+        self.set_buffer(completion + " ");
+    }
+
+    /// Whether the shell is reading commands from the terminal.
+    pub fn interactive(&self) -> bool {
+        self.stdout.is_some()
+    }
 
-        self.buffer = completion + " ";
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
     }
 
     pub fn history(&mut self) -> &mut History {
         &mut self.history
     }
 
+    fn initial_variables() -> Variables {
+        let mut variables = Variables::from_env();
+
+        if let Ok(current_dir) = std::env::current_dir() {
+            variables.set("PWD", current_dir.display().to_string());
+            variables.export("PWD");
+        }
+
+        variables
+    }
+
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
+    pub fn variables_mut(&mut self) -> &mut Variables {
+        &mut self.variables
+    }
+
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    pub fn flow(&self) -> Option<Flow> {
+        self.flow
+    }
+
+    pub fn set_flow(&mut self, flow: Flow) {
+        self.flow = Some(flow);
+    }
+
+    pub fn take_flow(&mut self) -> Option<Flow> {
+        self.flow.take()
+    }
+
+    /// How many loops the command being executed is nested in.
+    pub fn loop_depth(&self) -> usize {
+        self.loop_depth
+    }
+
+    pub fn set_loop_depth(&mut self, loop_depth: usize) {
+        self.loop_depth = loop_depth;
+    }
+
+    pub fn function(&self, name: &str) -> Option<Rc<Expr>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    pub fn define_function(&mut self, name: &str, body: Rc<Expr>) {
+        self.functions.insert(name.to_string(), body);
+    }
+
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    pub fn aliases_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.aliases
+    }
+
+    /// The positional parameters `$1`, `$2`, ...
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Replaces the positional parameters, returning the previous ones.
+    pub fn set_positional(&mut self, positional: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.positional, positional)
+    }
+
+    /// The command line read from the terminal that is being run, empty
+    /// while none is.
+    pub fn command_line(&self) -> &str {
+        &self.pending
+    }
+
+    /// How many function calls are being executed, which `return` needs.
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub fn set_call_depth(&mut self, call_depth: usize) {
+        self.call_depth = call_depth;
+    }
+
+    /// The state of the git repository around the current directory.
+    pub fn git_status(&mut self) -> Option<git::Status> {
+        let directory = PathBuf::from(self.variables.get("PWD")?);
+        self.git.status(&directory)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn arg0(&self) -> &str {
+        &self.arg0
+    }
+
+    pub fn set_arg0(&mut self, arg0: String) {
+        self.arg0 = arg0;
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
     fn read_history_file(&mut self) {
-        let Ok(path) = std::env::var("HISTFILE") else {
+        let Some(path) = self.variables.get("HISTFILE") else {
             return;
         };
 
@@ -257,14 +983,45 @@ impl Shell {
     }
 
     pub fn write_history_file(&mut self) {
-        let Ok(path) = std::env::var("HISTFILE") else {
+        let Some(path) = self.variables.get("HISTFILE") else {
             return;
         };
 
-        self.history.write_to_file(PathBuf::from(path));
+        self.history
+            .write_to_file(PathBuf::from(path), self.options.histcwd);
     }
 }
 
+/// Moves the cursor between two positions of the command line.
+fn move_cursor(from: (usize, usize), to: (usize, usize)) -> String {
+    let mut output = String::new();
+
+    if to.0 < from.0 {
+        write!(
+            output,
+            "{}",
+            cursor::Up(u16::try_from(from.0 - to.0).unwrap())
+        )
+        .unwrap();
+    } else if to.0 > from.0 {
+        write!(
+            output,
+            "{}",
+            cursor::Down(u16::try_from(to.0 - from.0).unwrap())
+        )
+        .unwrap();
+    }
+
+    if to.1 != from.1 {
+        output.push('\r');
+        if to.1 > 0 {
+            write!(output, "{}", cursor::Right(u16::try_from(to.1).unwrap())).unwrap();
+        }
+    }
+
+    output
+}
+
 fn completions_prefix(completions: &[String]) -> &str {
     completions
         .iter()
@@ -274,11 +1031,12 @@ fn completions_prefix(completions: &[String]) -> &str {
 }
 
 fn common_prefix<'a>(word1: &'a str, word2: &'a str) -> &'a str {
-    let i = word1
+    let length = word1
         .chars()
         .zip(word2.chars())
         .take_while(|(c1, c2)| c1 == c2)
-        .count();
+        .map(|(c, _)| c.len_utf8())
+        .sum();
 
-    &word1[..i]
+    &word1[..length]
 }
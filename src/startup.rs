@@ -0,0 +1,108 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    eval::error_message, expand::expand_to_string, shell::Shell, tokenizer::Tokenizer, users,
+};
+
+const PROFILE_FILES: [&str; 2] = [".shell_profile", ".profile"];
+const RC_FILE: &str = ".shellrc";
+
+/// How the shell was started, as given by its command line.
+#[derive(Debug, Default)]
+pub struct Invocation {
+    pub login: bool,
+    pub norc: bool,
+    pub rcfile: Option<PathBuf>,
+    pub posix: bool,
+    /// The script to run and its arguments.
+    pub args: Vec<String>,
+}
+
+impl Invocation {
+    /// Parses the options that come before the script, if any. A shell whose
+    /// `argv[0]` starts with `-` is a login shell, as `login` starts it.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut invocation = Self {
+            login: args.next().is_some_and(|arg0| arg0.starts_with('-')),
+            posix: std::env::var_os("POSIXLY_CORRECT").is_some(),
+            ..Self::default()
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-l" | "--login" => invocation.login = true,
+                "--norc" => invocation.norc = true,
+                "--posix" => invocation.posix = true,
+                "--rcfile" | "--init-file" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| format!("{arg}: option requires an argument"))?;
+                    invocation.rcfile = Some(PathBuf::from(file));
+                }
+                "--" => break,
+                option if option.starts_with('-') && option.len() > 1 => {
+                    return Err(format!("{option}: invalid option"));
+                }
+                _ => {
+                    invocation.args.push(arg);
+                    break;
+                }
+            }
+        }
+
+        invocation.args.extend(args);
+        Ok(invocation)
+    }
+}
+
+/// Runs the startup files: the login profile for a login shell, then for an
+/// interactive one the rc file, or the file named by `$ENV` in POSIX mode.
+pub fn load(shell: &mut Shell, invocation: &Invocation, interactive: bool) {
+    let home = shell
+        .variables()
+        .get("HOME")
+        .map(String::from)
+        .or_else(users::current_home_dir)
+        .map(PathBuf::from);
+
+    if invocation.login {
+        shell.source_file(&PathBuf::from("/etc/profile"));
+
+        // only the first of the profiles that exists is read
+        let profile = home.as_ref().and_then(|home| {
+            PROFILE_FILES
+                .iter()
+                .map(|file| home.join(file))
+                .find(|path| path.is_file())
+        });
+
+        if let Some(profile) = profile {
+            shell.source_file(&profile);
+        }
+    }
+
+    if !interactive {
+        return;
+    }
+
+    if invocation.posix {
+        let Some(env) = shell.variables().get("ENV").map(Tokenizer::operand) else {
+            return;
+        };
+
+        match expand_to_string(shell, &env) {
+            Ok(path) if !path.is_empty() => shell.source_file(&PathBuf::from(path)),
+            Ok(_) => {}
+            Err(e) => eprintln!("ENV: {e}"),
+        }
+    } else if let Some(rcfile) = &invocation.rcfile {
+        if let Err(e) = fs::metadata(rcfile) {
+            eprintln!("{}: {}", rcfile.display(), error_message(&e));
+        }
+        shell.source_file(rcfile);
+    } else if !invocation.norc {
+        if let Some(home) = home {
+            shell.source_file(&home.join(RC_FILE));
+        }
+    }
+}
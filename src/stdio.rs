@@ -0,0 +1,104 @@
+use std::{
+    fs::File,
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    mem::ManuallyDrop,
+    os::fd::FromRawFd,
+    process::Stdio,
+};
+
+/// The standard input handed to a command.
+pub enum Input {
+    Inherit,
+    File(File),
+    Pipe(PipeReader),
+}
+
+/// The standard output or standard error handed to a command.
+pub enum Output {
+    Stdout,
+    Stderr,
+    File(File),
+    Pipe(PipeWriter),
+}
+
+impl Input {
+    /// Duplicates the stream, so it can be handed to each command of a
+    /// compound command in turn.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Input::Inherit => Input::Inherit,
+            Input::File(file) => Input::File(file.try_clone()?),
+            Input::Pipe(pipe) => Input::Pipe(pipe.try_clone()?),
+        })
+    }
+}
+
+impl Output {
+    /// Duplicates the stream, so it can be handed to each command of a
+    /// compound command in turn.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Output::Stdout => Output::Stdout,
+            Output::Stderr => Output::Stderr,
+            Output::File(file) => Output::File(file.try_clone()?),
+            Output::Pipe(pipe) => Output::Pipe(pipe.try_clone()?),
+        })
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Inherit => {
+                // read the descriptor directly, so that nothing is buffered
+                // away from the commands that read the rest of the input
+                // SAFETY: standard input stays open for the whole process
+                let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+                stdin.read(buf)
+            }
+            Input::File(file) => file.read(buf),
+            Input::Pipe(pipe) => pipe.read(buf),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Stderr => io::stderr().write(buf),
+            Output::File(file) => file.write(buf),
+            Output::Pipe(pipe) => pipe.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => file.flush(),
+            Output::Pipe(pipe) => pipe.flush(),
+        }
+    }
+}
+
+impl From<Input> for Stdio {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Inherit => Stdio::inherit(),
+            Input::File(file) => file.into(),
+            Input::Pipe(pipe) => pipe.into(),
+        }
+    }
+}
+
+impl From<Output> for Stdio {
+    fn from(output: Output) -> Self {
+        match output {
+            Output::Stdout => io::stdout().into(),
+            Output::Stderr => io::stderr().into(),
+            Output::File(file) => file.into(),
+            Output::Pipe(pipe) => pipe.into(),
+        }
+    }
+}
@@ -1,14 +1,129 @@
+use std::{fmt, ops::Range};
+
+/// A shell word, split into segments so later expansion stages can tell which
+/// parts were quoted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Unquoted text, subject to expansion.
+    Literal(String),
+    /// Text that was quoted or escaped and must be taken verbatim.
+    Quoted(String),
+    /// The contents of a double-quoted string.
+    DoubleQuoted(Word),
+    /// The source of a `$(...)` or `` `...` `` command substitution.
+    CommandSubstitution(String),
+    /// The expression of a `$((...))` arithmetic expansion.
+    Arithmetic(String),
+    /// A `$name` or the text between the braces of `${...}`.
+    Parameter(String),
+}
+
+impl Word {
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The variable name, if the word has the form of an assignment.
+    pub fn assignment_name(&self) -> Option<&str> {
+        let Some(Segment::Literal(literal)) = self.segments.first() else {
+            return None;
+        };
+
+        literal
+            .split_once('=')
+            .map(|(name, _)| name)
+            .filter(|name| is_name(name))
+    }
+
+    /// The text of the word if it is entirely unquoted and free of
+    /// expansions.
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.segments.as_slice() {
+            [Segment::Literal(literal)] => Some(literal),
+            _ => None,
+        }
+    }
+
+    pub fn push_segment(&mut self, segment: Segment) {
+        match segment {
+            Segment::Literal(literal) => self.push_literal(&literal),
+            Segment::Quoted(quoted) => self.push_quoted(&quoted),
+            segment => self.segments.push(segment),
+        }
+    }
+
+    pub fn push_literal(&mut self, s: &str) {
+        match self.segments.last_mut() {
+            Some(Segment::Literal(literal)) => literal.push_str(s),
+            _ => self.segments.push(Segment::Literal(s.to_string())),
+        }
+    }
+
+    pub fn push_quoted(&mut self, s: &str) {
+        match self.segments.last_mut() {
+            Some(Segment::Quoted(quoted)) => quoted.push_str(s),
+            _ => self.segments.push(Segment::Quoted(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) | Segment::Quoted(text) => write!(f, "{text}")?,
+                Segment::DoubleQuoted(word) => write!(f, "\"{word}\"")?,
+                Segment::CommandSubstitution(source) => write!(f, "$({source})")?,
+                Segment::Arithmetic(expression) => write!(f, "$(({expression}))")?,
+                Segment::Parameter(text) => write!(f, "${{{text}}}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 pub struct Tokenizer {
     chars: Vec<char>,
     position: usize,
     tokens: Vec<Token>,
+    /// The range of characters each token was read from.
+    spans: Vec<Range<usize>>,
+    /// Whether a quote or substitution was still open at the end of the
+    /// input.
+    unterminated: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum Token {
     Ampersand,
+    DoubleAmpersand,
+    DoublePipe,
+    Semicolon,
+    DoubleSemicolon,
+    SemicolonAmpersand,
+    DoubleSemicolonAmpersand,
+    LeftParen,
+    RightParen,
+    Newline,
     Greater,
-    Literal(String),
+    Less,
+    Literal(Word),
+    Arithmetic(String),
     OneGreater,
     Pipe,
     TwoGreater,
@@ -19,12 +134,60 @@ pub enum Token {
     TwoDoubleGreater,
 }
 
+impl Token {
+    pub fn is_redirection(&self) -> bool {
+        matches!(
+            self,
+            Token::Greater
+                | Token::Less
+                | Token::OneGreater
+                | Token::TwoGreater
+                | Token::ZeroGreater
+                | Token::DoubleGreater
+                | Token::OneDoubleGreater
+                | Token::TwoDoubleGreater
+                | Token::ZeroDoubleGreater
+        )
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ampersand => write!(f, "&"),
+            Token::DoubleAmpersand => write!(f, "&&"),
+            Token::DoublePipe => write!(f, "||"),
+            Token::Semicolon => write!(f, ";"),
+            Token::DoubleSemicolon => write!(f, ";;"),
+            Token::SemicolonAmpersand => write!(f, ";&"),
+            Token::DoubleSemicolonAmpersand => write!(f, ";;&"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Newline => write!(f, "newline"),
+            Token::Greater => write!(f, ">"),
+            Token::Less => write!(f, "<"),
+            Token::Literal(word) => write!(f, "{word}"),
+            Token::Arithmetic(expression) => write!(f, "(({expression}))"),
+            Token::OneGreater => write!(f, "1>"),
+            Token::Pipe => write!(f, "|"),
+            Token::TwoGreater => write!(f, "2>"),
+            Token::ZeroGreater => write!(f, "0>"),
+            Token::ZeroDoubleGreater => write!(f, "0>>"),
+            Token::OneDoubleGreater => write!(f, "1>>"),
+            Token::DoubleGreater => write!(f, ">>"),
+            Token::TwoDoubleGreater => write!(f, "2>>"),
+        }
+    }
+}
+
 impl Tokenizer {
     pub fn new(input: &str) -> Self {
         Self {
             chars: input.chars().collect(),
             position: 0,
             tokens: Vec::new(),
+            spans: Vec::new(),
+            unterminated: false,
         }
     }
 
@@ -38,54 +201,126 @@ impl Tokenizer {
         c
     }
 
+    /// Notes whether the construct just read ran out of input before its
+    /// closing character, which leaves the position past the end.
+    fn check_terminated(&mut self) {
+        if self.position > self.chars.len() {
+            self.unterminated = true;
+        }
+    }
+
     pub fn parse(&mut self) {
-        while let Some(char) = self.peek() {
-            match char {
-                ' ' => self.whitespace(),
-                '>' if self.check_nth_ahead(1, '>') => {
-                    self.tokens.push(Token::DoubleGreater);
-                    self.consume_tokens(2);
-                }
-                '>' => {
-                    self.next().unwrap();
-                    self.tokens.push(Token::Greater);
-                }
-                '0' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
-                    self.tokens.push(Token::ZeroDoubleGreater);
-                    self.consume_tokens(3);
-                }
-                '1' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
-                    self.tokens.push(Token::OneDoubleGreater);
-                    self.consume_tokens(3);
-                }
-                '2' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
-                    self.tokens.push(Token::TwoDoubleGreater);
-                    self.consume_tokens(3);
-                }
-                '0' if self.check_nth_ahead(1, '>') => {
-                    self.tokens.push(Token::ZeroGreater);
-                    self.consume_tokens(2);
-                }
-                '1' if self.check_nth_ahead(1, '>') => {
-                    self.next().unwrap();
-                    self.next().unwrap();
-                    self.tokens.push(Token::OneGreater);
-                }
-                '2' if self.chars.get(self.position + 1) == Some(&'>') => {
-                    self.next().unwrap();
-                    self.next().unwrap();
-                    self.tokens.push(Token::TwoGreater);
-                }
-                '|' => {
-                    self.next().unwrap();
-                    self.tokens.push(Token::Pipe);
-                }
-                '&' => {
+        while self.position < self.chars.len() {
+            let start = self.position;
+            self.token();
+
+            if self.spans.len() < self.tokens.len() {
+                self.spans.push(start..self.position.min(self.chars.len()));
+            }
+        }
+    }
+
+    /// Reads the token at the current position, or skips blanks or a comment.
+    fn token(&mut self) {
+        let Some(&char) = self.peek() else {
+            return;
+        };
+
+        match char {
+            ' ' | '\t' => self.whitespace(),
+            '#' => self.comment(),
+            '\\' if self.check_nth_ahead(1, '\n') => self.consume_tokens(2),
+            ';' if self.check_nth_ahead(1, ';') && self.check_nth_ahead(2, '&') => {
+                self.tokens.push(Token::DoubleSemicolonAmpersand);
+                self.consume_tokens(3);
+            }
+            ';' if self.check_nth_ahead(1, ';') => {
+                self.tokens.push(Token::DoubleSemicolon);
+                self.consume_tokens(2);
+            }
+            ';' if self.check_nth_ahead(1, '&') => {
+                self.tokens.push(Token::SemicolonAmpersand);
+                self.consume_tokens(2);
+            }
+            ';' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Semicolon);
+            }
+            '\n' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Newline);
+            }
+            '>' if self.check_nth_ahead(1, '>') => {
+                self.tokens.push(Token::DoubleGreater);
+                self.consume_tokens(2);
+            }
+            '>' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Greater);
+            }
+            '<' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Less);
+            }
+            '0' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
+                self.tokens.push(Token::ZeroDoubleGreater);
+                self.consume_tokens(3);
+            }
+            '1' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
+                self.tokens.push(Token::OneDoubleGreater);
+                self.consume_tokens(3);
+            }
+            '2' if self.check_nth_ahead(1, '>') && self.check_nth_ahead(2, '>') => {
+                self.tokens.push(Token::TwoDoubleGreater);
+                self.consume_tokens(3);
+            }
+            '0' if self.check_nth_ahead(1, '>') => {
+                self.tokens.push(Token::ZeroGreater);
+                self.consume_tokens(2);
+            }
+            '1' if self.check_nth_ahead(1, '>') => {
+                self.next().unwrap();
+                self.next().unwrap();
+                self.tokens.push(Token::OneGreater);
+            }
+            '2' if self.chars.get(self.position + 1) == Some(&'>') => {
+                self.next().unwrap();
+                self.next().unwrap();
+                self.tokens.push(Token::TwoGreater);
+            }
+            '|' if self.check_nth_ahead(1, '|') => {
+                self.tokens.push(Token::DoublePipe);
+                self.consume_tokens(2);
+            }
+            '|' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Pipe);
+            }
+            '(' if self.check_nth_ahead(1, '(') => {
+                if let Some(expression) = self.arithmetic_expression() {
+                    self.tokens.push(Token::Arithmetic(expression));
+                } else {
                     self.next().unwrap();
-                    self.tokens.push(Token::Ampersand);
+                    self.tokens.push(Token::LeftParen);
                 }
-                _ => self.literal(),
             }
+            '(' => {
+                self.next().unwrap();
+                self.tokens.push(Token::LeftParen);
+            }
+            ')' => {
+                self.next().unwrap();
+                self.tokens.push(Token::RightParen);
+            }
+            '&' if self.check_nth_ahead(1, '&') => {
+                self.tokens.push(Token::DoubleAmpersand);
+                self.consume_tokens(2);
+            }
+            '&' => {
+                self.next().unwrap();
+                self.tokens.push(Token::Ampersand);
+            }
+            _ => self.literal(),
         }
     }
 
@@ -98,27 +333,163 @@ impl Tokenizer {
     }
 
     fn literal(&mut self) {
-        let mut literal = String::new();
+        let word = self.word(|c| {
+            matches!(
+                c,
+                ' ' | '\t' | '\n' | ';' | '<' | '>' | '&' | '|' | '(' | ')'
+            )
+        });
+        self.tokens.push(Token::Literal(word));
+    }
 
-        while let Some(char) = self.peek() {
+    /// Parses the whole of `text` as if it were inside double quotes that
+    /// `"` doesn't close, as needed for prompt strings.
+    pub fn double_quoted(text: &str) -> Word {
+        let mut word = Word::default();
+        word.push_segment(Segment::DoubleQuoted(
+            Self::new(text).double_quote_contents(false),
+        ));
+        word
+    }
+
+    /// Parses the whole of `text` as a single word, as needed for the operands
+    /// of parameter expansions.
+    pub fn operand(text: &str) -> Word {
+        Self::new(text).word(|_| false)
+    }
+
+    fn word(&mut self, is_terminator: fn(char) -> bool) -> Word {
+        let mut word = Word::default();
+
+        while let Some(&char) = self.peek() {
             match char {
-                &' ' | &'>' | &'&' | '|' => break,
-                &'\'' => literal.push_str(&self.single_quote_literal()),
-                &'\"' => literal.push_str(&self.double_quote_literal()),
-                &'\\' => {
+                c if is_terminator(c) => break,
+                '\'' => word.push_quoted(&self.single_quote_literal()),
+                '\"' => word.push_segment(Segment::DoubleQuoted(self.double_quote_literal())),
+                '$' => match self.dollar() {
+                    Some(segment) => word.push_segment(segment),
+                    None => word.push_literal("$"),
+                },
+                '`' => {
+                    word.push_segment(Segment::CommandSubstitution(self.backquote_substitution()));
+                }
+                '\\' if self.check_nth_ahead(1, '\n') => self.consume_tokens(2),
+                '\\' => {
                     // consume backslash
                     self.next().unwrap();
-                    literal.push(
-                        *self
-                            .next()
-                            .expect("expected escaped character after backslash"),
-                    );
+                    match self.next() {
+                        Some(escaped) => word.push_quoted(&escaped.to_string()),
+                        None => word.push_literal("\\"),
+                    }
+                }
+                '?' | '*' | '+' | '@' | '!' if self.check_nth_ahead(1, '(') => {
+                    word.push_literal(&self.extglob_group());
                 }
-                _ => literal.push(*self.next().unwrap()),
+                _ => word.push_literal(&self.next().unwrap().to_string()),
             }
         }
 
-        self.tokens.push(Token::Literal(literal));
+        word
+    }
+
+    /// Reads an expansion introduced by `$`, or consumes just the `$` and
+    /// returns `None` if it doesn't start one.
+    fn dollar(&mut self) -> Option<Segment> {
+        match self.chars.get(self.position + 1) {
+            Some('(') if self.check_nth_ahead(2, '(') => {
+                Some(self.arithmetic_or_command_substitution())
+            }
+            Some('(') => Some(Segment::CommandSubstitution(self.command_substitution())),
+            Some('{') => Some(Segment::Parameter(self.braced_parameter())),
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                // consume `$`
+                self.next();
+                let mut name = String::new();
+
+                while let Some(&c) = self.peek() {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    name.push(c);
+                    self.next();
+                }
+
+                Some(Segment::Parameter(name))
+            }
+            Some(&c) if c.is_ascii_digit() || "?$#!@*-".contains(c) => {
+                self.consume_tokens(2);
+                Some(Segment::Parameter(c.to_string()))
+            }
+            _ => {
+                self.next();
+                None
+            }
+        }
+    }
+
+    fn braced_parameter(&mut self) -> String {
+        let mut text = String::new();
+        let mut depth = 1;
+
+        // consume `${`
+        self.consume_tokens(2);
+
+        while let Some(&char) = self.next() {
+            match char {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                '\\' => {
+                    text.push(char);
+                    text.extend(self.next());
+                    continue;
+                }
+                '\'' => {
+                    text.push(char);
+                    while let Some(&c) = self.next() {
+                        text.push(c);
+                        if c == '\'' {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            text.push(char);
+        }
+
+        self.check_terminated();
+        text
+    }
+
+    fn extglob_group(&mut self) -> String {
+        // consume the operator and the opening parenthesis
+        let mut group: String = [*self.next().unwrap(), *self.next().unwrap()]
+            .into_iter()
+            .collect();
+        let mut depth = 1;
+
+        while let Some(&char) = self.next() {
+            group.push(char);
+
+            match char {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+
+            if depth == 0 {
+                break;
+            }
+        }
+
+        group
     }
 
     fn single_quote_literal(&mut self) -> String {
@@ -134,36 +505,188 @@ impl Tokenizer {
             }
         }
 
+        self.check_terminated();
         literal
     }
 
-    fn double_quote_literal(&mut self) -> String {
-        let mut literal = String::new();
-
+    fn double_quote_literal(&mut self) -> Word {
         // consume opening quote
         self.next();
 
-        while let Some(char) = self.next() {
+        self.double_quote_contents(true)
+    }
+
+    /// Reads the inside of a double-quoted string, up to the closing quote
+    /// if `closed` and to the end of the input otherwise.
+    fn double_quote_contents(&mut self, closed: bool) -> Word {
+        let mut word = Word::default();
+
+        loop {
+            let Some(char) = self.peek() else {
+                self.unterminated |= closed;
+                break;
+            };
+
             match char {
-                &'"' => break,
-                &'\\' => match self.next() {
-                    Some('\\') => literal.push('\\'),
-                    Some('\"') => literal.push('\"'),
-                    Some(c) => {
-                        literal.push('\\');
-                        literal.push(*c);
+                &'"' if closed => {
+                    self.next();
+                    break;
+                }
+                '$' => match self.dollar() {
+                    Some(segment) => word.push_segment(segment),
+                    None => word.push_quoted("$"),
+                },
+                '`' => {
+                    word.push_segment(Segment::CommandSubstitution(self.backquote_substitution()));
+                }
+                &'\\' => {
+                    self.next();
+                    match self.next() {
+                        Some(c @ ('\\' | '"' | '$' | '`')) => word.push_quoted(&c.to_string()),
+                        Some(c) => word.push_quoted(&format!("\\{c}")),
+                        None => word.push_quoted("\\"),
                     }
-                    None => panic!("expected character after backslash"),
+                }
+                c => {
+                    let c = c.to_string();
+                    self.next();
+                    word.push_quoted(&c);
+                }
+            }
+        }
+
+        word
+    }
+
+    fn arithmetic_or_command_substitution(&mut self) -> Segment {
+        // consume `$`
+        self.next();
+
+        if let Some(expression) = self.arithmetic_expression() {
+            Segment::Arithmetic(expression)
+        } else {
+            self.position -= 1;
+            Segment::CommandSubstitution(self.command_substitution())
+        }
+    }
+
+    /// Reads `((expression))`, or returns `None` and leaves the position
+    /// untouched if the parentheses don't close with `))`.
+    fn arithmetic_expression(&mut self) -> Option<String> {
+        let start = self.position;
+        let mut expression = String::new();
+        let mut depth = 0;
+
+        // consume `((`
+        self.consume_tokens(2);
+
+        while let Some(&char) = self.next() {
+            match char {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    if self.peek() == Some(&')') {
+                        self.next();
+                        return Some(expression);
+                    }
+                    break;
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+
+            expression.push(char);
+        }
+
+        self.position = start;
+        None
+    }
+
+    fn command_substitution(&mut self) -> String {
+        let mut source = String::new();
+        let mut depth = 1;
+
+        // consume `$(`
+        self.consume_tokens(2);
+
+        while let Some(&char) = self.next() {
+            match char {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                '\'' => {
+                    source.push(char);
+                    while let Some(&c) = self.next() {
+                        source.push(c);
+                        if c == '\'' {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                '"' => {
+                    source.push(char);
+                    while let Some(&c) = self.next() {
+                        source.push(c);
+                        match c {
+                            '"' => break,
+                            '\\' => source.extend(self.next()),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                '\\' => {
+                    source.push(char);
+                    source.extend(self.next());
+                    continue;
+                }
+                _ => {}
+            }
+
+            source.push(char);
+        }
+
+        self.check_terminated();
+        source
+    }
+
+    fn backquote_substitution(&mut self) -> String {
+        let mut source = String::new();
+
+        // consume opening backquote
+        self.next();
+
+        while let Some(&char) = self.next() {
+            match char {
+                '`' => break,
+                '\\' => match self.next() {
+                    Some(&c @ ('\\' | '`' | '$')) => source.push(c),
+                    Some(&c) => {
+                        source.push('\\');
+                        source.push(c);
+                    }
+                    None => source.push('\\'),
                 },
-                c => literal.push(*c),
+                c => source.push(c),
             }
         }
 
-        literal
+        self.check_terminated();
+        source
     }
 
     fn whitespace(&mut self) {
-        while let Some(&' ') = self.peek() {
+        while let Some(' ' | '\t') = self.peek() {
+            self.next().unwrap();
+        }
+    }
+
+    fn comment(&mut self) {
+        while self.peek().is_some_and(|&c| c != '\n') {
             self.next().unwrap();
         }
     }
@@ -177,4 +700,20 @@ impl Tokenizer {
         tokenizer.parse();
         tokenizer.tokens()
     }
+
+    /// Tokenizes `input`, or returns `None` if a quote or substitution is
+    /// still open at its end and more lines are needed.
+    pub fn tokenize_complete(input: &str) -> Option<Vec<Token>> {
+        let mut tokenizer = Self::new(input);
+        tokenizer.parse();
+        (!tokenizer.unterminated).then(|| tokenizer.tokens())
+    }
+
+    /// Tokenizes `input`, which may be an incomplete command, pairing every
+    /// token with the range of characters it was read from.
+    pub fn tokenize_with_spans(input: &str) -> Vec<(Token, Range<usize>)> {
+        let mut tokenizer = Self::new(input);
+        tokenizer.parse();
+        tokenizer.tokens.into_iter().zip(tokenizer.spans).collect()
+    }
 }
@@ -0,0 +1,76 @@
+use std::{
+    ffi::{CStr, CString, c_char},
+    mem, ptr,
+};
+
+/// The home directory of `user` according to the user database.
+pub fn home_dir(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let passwd = lookup(|passwd, buffer, result| unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            result,
+        )
+    })?;
+
+    Some(passwd.dir)
+}
+
+/// The home directory of the user running the shell according to the user
+/// database.
+pub fn current_home_dir() -> Option<String> {
+    current_user().map(|passwd| passwd.dir)
+}
+
+/// The login name of the user running the shell according to the user
+/// database.
+pub fn current_user_name() -> Option<String> {
+    current_user().map(|passwd| passwd.name)
+}
+
+/// The fields of a user database entry that the shell needs.
+struct Passwd {
+    name: String,
+    dir: String,
+}
+
+fn current_user() -> Option<Passwd> {
+    lookup(|passwd, buffer, result| unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            result,
+        )
+    })
+}
+
+fn lookup(
+    query: impl FnOnce(&mut libc::passwd, &mut [c_char], &mut *mut libc::passwd) -> i32,
+) -> Option<Passwd> {
+    // SAFETY: `passwd` is plain old data that `query` fills in
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0; 4096];
+    let mut result = ptr::null_mut();
+
+    if query(&mut passwd, &mut buffer, &mut result) != 0 || result.is_null() {
+        return None;
+    }
+
+    // SAFETY: on success the fields point to C strings inside `buffer`
+    let (name, dir) = unsafe {
+        (
+            CStr::from_ptr(passwd.pw_name),
+            CStr::from_ptr(passwd.pw_dir),
+        )
+    };
+
+    Some(Passwd {
+        name: name.to_string_lossy().into_owned(),
+        dir: dir.to_string_lossy().into_owned(),
+    })
+}
@@ -0,0 +1,135 @@
+use std::{collections::HashMap, env};
+
+#[derive(Clone)]
+pub struct Variable {
+    value: String,
+    exported: bool,
+    /// The elements of an array variable, whose value is its first element.
+    elements: Vec<String>,
+}
+
+/// The shell's variables, seeded from the environment it was started with.
+pub struct Variables {
+    values: HashMap<String, Variable>,
+    /// For every function call being executed, the variables its locals
+    /// shadow.
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        let values = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value,
+                    exported: true,
+                    elements: Vec::new(),
+                };
+                (name, variable)
+            })
+            .collect();
+
+        Self {
+            values,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .map(|variable| variable.value.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values
+            .entry(name.to_string())
+            .and_modify(|variable| {
+                variable.value.clone_from(&value);
+                variable.elements.clear();
+            })
+            .or_insert(Variable {
+                value,
+                exported: false,
+                elements: Vec::new(),
+            });
+    }
+
+    pub fn set_array(&mut self, name: &str, elements: Vec<String>) {
+        let value = elements.first().cloned().unwrap_or_default();
+        self.set(name, value);
+
+        if let Some(variable) = self.values.get_mut(name) {
+            variable.elements = elements;
+        }
+    }
+
+    /// The elements of a variable, where a scalar is an array of one.
+    pub fn elements(&self, name: &str) -> Vec<&str> {
+        match self.values.get(name) {
+            Some(variable) if variable.elements.is_empty() => vec![variable.value.as_str()],
+            Some(variable) => variable.elements.iter().map(String::as_str).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    /// A copy of a variable, to be put back with [`Variables::restore`].
+    pub fn snapshot(&self, name: &str) -> Option<Variable> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn restore(&mut self, name: &str, variable: Option<Variable>) {
+        match variable {
+            Some(variable) => self.values.insert(name.to_string(), variable),
+            None => self.values.remove(name),
+        };
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Ends the innermost scope, putting back the variables its locals
+    /// shadowed.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+
+        for (name, variable) in scope {
+            self.restore(&name, variable);
+        }
+    }
+
+    /// Makes a variable local to the innermost scope, so that it is restored
+    /// when the scope ends. Returns `false` outside of any scope.
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+
+        if !scope.contains_key(name) {
+            scope.insert(name.to_string(), self.values.get(name).cloned());
+        }
+
+        true
+    }
+
+    pub fn export(&mut self, name: &str) {
+        if let Some(variable) = self.values.get_mut(name) {
+            variable.exported = true;
+        }
+    }
+
+    /// The variables passed on to the environment of child processes.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .map(|(name, variable)| (name.as_str(), variable.value.as_str()))
+    }
+}